@@ -1,70 +1,55 @@
 #![allow(dead_code)]
 
-use std::collections::HashMap;
 use std::env;
 
 use chrono::{DateTime, Datelike, FixedOffset, TimeZone, Timelike};
 
+#[path = "../src/accuracy.rs"]
+mod accuracy;
 #[path = "../src/constants.rs"]
 mod constants;
 #[path = "../src/predictor.rs"]
 mod predictor;
+#[path = "../src/tuning.rs"]
+mod tuning;
 
 use constants::{ALL_STATES_DETAILS, STATE_COUNT};
-use predictor::{ActivityPredictor, Configuration, Ranking, TrainingEntry, WeekdayMode};
+use predictor::{
+    ActivityPredictor, Configuration, DurationEstimate, FrequencyRanker, MarkovHourRanker,
+    MarkovRanker, Ranker, Ranking, RecencyRanker, TrainingEntry,
+};
+use tuning::{Metrics, ablations, eval_window, grid, ratio, stage_two, tune};
 
 #[derive(Default, Clone)]
-struct Metrics {
+struct DurationMetrics {
     scored: usize,
-    skipped_self: usize,
-    top1: usize,
-    top3: usize,
-    reciprocal_rank: f64,
+    unestimated: usize,
+    absolute_error: f64,
+    within_quartiles: usize,
 }
 
-impl Metrics {
-    fn record(&mut self, truth: usize, predictions: &[usize], current: Option<usize>) {
-        if current == Some(truth) {
-            self.skipped_self += 1;
+impl DurationMetrics {
+    fn record(&mut self, actual: i64, estimate: Option<DurationEstimate>) {
+        let Some(estimate) = estimate else {
+            self.unestimated += 1;
             return;
-        }
+        };
         self.scored += 1;
-        if predictions.first() == Some(&truth) {
-            self.top1 += 1;
+        self.absolute_error += (estimate.median - actual).abs() as f64;
+        if estimate.p25 <= actual && actual <= estimate.p75 {
+            self.within_quartiles += 1;
         }
-        if let Some(rank) = predictions.iter().position(|&s| s == truth) {
-            if rank < 3 {
-                self.top3 += 1;
-            }
-            self.reciprocal_rank += 1.0 / (rank as f64 + 1.0);
-        }
-    }
-
-    fn top1_rate(&self) -> f64 {
-        ratio(self.top1, self.scored)
-    }
-
-    fn top3_rate(&self) -> f64 {
-        ratio(self.top3, self.scored)
     }
 
-    fn mrr(&self) -> f64 {
+    fn mae_minutes(&self) -> f64 {
         if self.scored == 0 {
             0.0
         } else {
-            self.reciprocal_rank / self.scored as f64
+            self.absolute_error / self.scored as f64 / 60_000.0
         }
     }
 }
 
-fn ratio(numerator: usize, denominator: usize) -> f64 {
-    if denominator == 0 {
-        0.0
-    } else {
-        100.0 * numerator as f64 / denominator as f64
-    }
-}
-
 fn load_json(path: &str) -> Vec<TrainingEntry> {
     let text = std::fs::read_to_string(path).expect("read export");
     let document: serde_json::Value = serde_json::from_str(&text).expect("parse export");
@@ -201,112 +186,71 @@ fn eval_tage(
     warmup: usize,
     limit: usize,
 ) -> Metrics {
-    eval_tage_window(entries, offset, configuration, warmup, entries.len(), limit)
+    eval_window(entries, offset, configuration, warmup, entries.len(), limit)
 }
 
-fn eval_tage_window(
+/// Scores the duration estimate made the moment each entry starts against how
+/// long it actually ran, i.e. until the next entry.
+fn eval_durations(
     entries: &[TrainingEntry],
     offset: FixedOffset,
     configuration: Configuration,
-    score_from: usize,
-    score_to: usize,
-    limit: usize,
-) -> Metrics {
+    warmup: usize,
+) -> DurationMetrics {
     let mut predictor = ActivityPredictor::new(&[], offset, configuration);
-    let mut metrics = Metrics::default();
+    let mut metrics = DurationMetrics::default();
     for (i, entry) in entries.iter().enumerate() {
-        if i >= score_from && i < score_to {
-            let current = entries[i - 1].state_id;
-            let predictions = predictor.predictions(entry.start_timestamp, Some(current), limit);
-            metrics.record(entry.state_id, &predictions, Some(current));
-        }
         predictor.train(entry.state_id, entry.start_timestamp);
+        if i < warmup || i + 1 == entries.len() {
+            continue;
+        }
+        let open = predictor.open_entry().unwrap();
+        let estimate = predictor.duration_estimate(
+            open.state_id,
+            open.started_at,
+            open.previous_state_id,
+            open.started_at,
+        );
+        metrics.record(
+            entries[i + 1].start_timestamp - entry.start_timestamp,
+            estimate,
+        );
     }
     metrics
 }
 
-fn eval_baseline<F>(entries: &[TrainingEntry], warmup: usize, limit: usize, mut rank: F) -> Metrics
-where
-    F: FnMut(&[TrainingEntry], usize, Option<usize>) -> Vec<usize>,
-{
-    let mut metrics = Metrics::default();
-    for i in warmup..entries.len() {
-        let current = entries[i - 1].state_id;
-        let mut ranked = rank(entries, i, Some(current));
-        ranked.retain(|&s| s != current);
-        ranked.truncate(limit);
-        metrics.record(entries[i].state_id, &ranked, Some(current));
-    }
-    metrics
-}
-
-fn rank_global_frequency(entries: &[TrainingEntry], upto: usize, _current: Option<usize>) -> Vec<usize> {
-    let mut counts = [0usize; STATE_COUNT];
-    for entry in &entries[..upto] {
-        counts[entry.state_id] += 1;
-    }
-    let mut ranked: Vec<usize> = (0..STATE_COUNT).collect();
-    ranked.sort_by_key(|&s| std::cmp::Reverse(counts[s]));
-    ranked
-}
-
-fn rank_recency(entries: &[TrainingEntry], upto: usize, _current: Option<usize>) -> Vec<usize> {
-    let mut last_seen = [usize::MAX; STATE_COUNT];
-    for (i, entry) in entries[..upto].iter().enumerate() {
-        last_seen[entry.state_id] = i;
-    }
-    let mut ranked: Vec<usize> = (0..STATE_COUNT).collect();
-    ranked.sort_by_key(|&s| std::cmp::Reverse(last_seen[s].wrapping_add(1)));
-    ranked
-}
-
-fn rank_markov(entries: &[TrainingEntry], upto: usize, current: Option<usize>) -> Vec<usize> {
-    let Some(current) = current else {
-        return rank_global_frequency(entries, upto, None);
-    };
-    let mut counts = [0usize; STATE_COUNT];
-    let mut totals = [0usize; STATE_COUNT];
-    for pair in entries[..upto].windows(2) {
-        if pair[0].state_id == current {
-            counts[pair[1].state_id] += 1;
-        }
-        totals[pair[1].state_id] += 1;
-    }
-    let mut ranked: Vec<usize> = (0..STATE_COUNT).collect();
-    ranked.sort_by_key(|&s| std::cmp::Reverse((counts[s], totals[s])));
-    ranked
+fn report_durations(label: &str, metrics: &DurationMetrics) {
+    println!(
+        "{:<28} mae {:>6.1} min   p25-p75 {:>5.1}%   n={} (no estimate {})",
+        label,
+        metrics.mae_minutes(),
+        ratio(metrics.within_quartiles, metrics.scored),
+        metrics.scored,
+        metrics.unestimated
+    );
 }
 
-fn rank_markov_hour(entries: &[TrainingEntry], upto: usize, current: Option<usize>) -> Vec<usize> {
-    let Some(current) = current else {
-        return rank_global_frequency(entries, upto, None);
-    };
-    let offset = FixedOffset::east_opt(0).unwrap();
-    let target_hour = offset
-        .timestamp_millis_opt(entries[upto].start_timestamp)
-        .single()
-        .map(|t| t.hour() / 4)
-        .unwrap_or(0);
-    let mut counts: HashMap<usize, usize> = HashMap::new();
-    let mut fallback = [0usize; STATE_COUNT];
-    for pair in entries[..upto].windows(2) {
+fn eval_baseline(
+    entries: &[TrainingEntry],
+    offset: FixedOffset,
+    warmup: usize,
+    limit: usize,
+    mut ranker: impl Ranker,
+) -> Metrics {
+    let mut metrics = Metrics::default();
+    for (i, entry) in entries.iter().enumerate() {
         let hour = offset
-            .timestamp_millis_opt(pair[1].start_timestamp)
+            .timestamp_millis_opt(entry.start_timestamp)
             .single()
-            .map(|t| t.hour() / 4)
-            .unwrap_or(0);
-        if pair[0].state_id == current && hour == target_hour {
-            *counts.entry(pair[1].state_id).or_default() += 1;
-        }
-        if pair[0].state_id == current {
-            fallback[pair[1].state_id] += 1;
+            .map_or(0, |t| t.hour());
+        let previous = i.checked_sub(1).map(|p| entries[p].state_id);
+        if i >= warmup.max(1) {
+            let ranked = ranker.rank(previous, hour, limit);
+            metrics.record(entry.state_id, &ranked, previous);
         }
+        ranker.observe(previous, entry.state_id, hour);
     }
-    let mut ranked: Vec<usize> = (0..STATE_COUNT).collect();
-    ranked.sort_by_key(|&s| {
-        std::cmp::Reverse((counts.get(&s).copied().unwrap_or(0), fallback[s]))
-    });
-    ranked
+    metrics
 }
 
 fn report(label: &str, metrics: &Metrics) {
@@ -353,71 +297,52 @@ fn main() {
 
     report(
         "baseline: frequency",
-        &eval_baseline(&entries, warmup, limit, rank_global_frequency),
+        &eval_baseline(&entries, offset, warmup, limit, FrequencyRanker::default()),
     );
     report(
         "baseline: recency",
-        &eval_baseline(&entries, warmup, limit, rank_recency),
+        &eval_baseline(&entries, offset, warmup, limit, RecencyRanker::default()),
     );
     report(
         "baseline: markov-1",
-        &eval_baseline(&entries, warmup, limit, rank_markov),
+        &eval_baseline(&entries, offset, warmup, limit, MarkovRanker::default()),
     );
     report(
         "baseline: markov-1 x hour/4",
-        &eval_baseline(&entries, warmup, limit, rank_markov_hour),
+        &eval_baseline(&entries, offset, warmup, limit, MarkovHourRanker::new(4)),
     );
     if mode == "grid" || mode == "stage2" {
-        let selection_from = warmup;
-        let selection_to = warmup + (entries.len() - warmup) / 2;
-        let candidates = if mode == "grid" { grid() } else { stage_two() };
-        let mut scored: Vec<(f64, f64, String, Configuration)> = candidates
-            .into_iter()
-            .map(|(label, configuration)| {
-                let metrics = eval_tage_window(
-                    &entries,
-                    offset,
-                    configuration.clone(),
-                    selection_from,
-                    selection_to,
-                    limit,
-                );
-                (metrics.top3_rate(), metrics.top1_rate(), label, configuration)
-            })
-            .collect();
-        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap().then(b.1.partial_cmp(&a.1).unwrap()));
+        let candidates = if mode == "grid" {
+            grid()
+        } else {
+            stage_two(&Configuration::default())
+        };
+        let shown = if mode == "grid" { 15 } else { candidates.len() };
+        let report = tune(&entries, offset, candidates, warmup, shown, limit, |_| {});
 
         println!();
         println!(
             "== grid: selection window {}..{}, held-out {}..{} ==",
-            selection_from,
-            selection_to,
-            selection_to,
-            entries.len()
+            report.selection_from, report.held_out_from, report.held_out_from, report.held_out_to
         );
-        let shown = if mode == "grid" { 15 } else { scored.len() };
-        for (top3, top1, label, configuration) in scored.iter().take(shown) {
-            let held_out = eval_tage_window(
-                &entries,
-                offset,
-                configuration.clone(),
-                selection_to,
-                entries.len(),
-                limit,
-            );
+        for candidate in report.candidates.iter().take(shown) {
+            let held_out = candidate.held_out.unwrap_or_default();
             println!(
-                "{label:<52} sel top3 {top3:>5.1}% top1 {top1:>5.1}%  |  held-out top3 {:>5.1}% top1 {:>5.1}%",
-                held_out.top3_rate(),
-                held_out.top1_rate()
+                "{:<52} sel top3 {:>5.1}% top1 {:>5.1}%  |  held-out top3 {:>5.1}% top1 {:>5.1}%",
+                candidate.label,
+                100.0 * candidate.selection.top3,
+                100.0 * candidate.selection.top1,
+                100.0 * held_out.top3,
+                100.0 * held_out.top1
             );
         }
 
-        let legacy_held_out = eval_tage_window(
+        let legacy_held_out = eval_window(
             &entries,
             offset,
             Configuration::legacy(),
-            selection_to,
-            entries.len(),
+            report.held_out_from,
+            report.held_out_to,
             limit,
         );
         println!(
@@ -446,242 +371,75 @@ fn main() {
         "tuned default",
         &eval_tage(&entries, offset, Configuration::default(), warmup, limit),
     );
-}
-
-fn stage_two() -> Vec<(String, Configuration)> {
-    let mut variants = vec![("tuned default".to_string(), Configuration::default())];
-
-    for size in [1_024, 4_096, 16_384] {
-        variants.push((
-            format!("tables {size}"),
-            Configuration {
-                base_table_size: size,
-                tagged_table_size: size,
-                ..Configuration::default()
-            },
-        ));
-    }
-    variants.push((
-        "elapsed context".to_string(),
-        Configuration {
-            elapsed_context: true,
-            ..Configuration::default()
-        },
-    ));
-    for lengths in [vec![1, 2, 3, 4], vec![1, 2, 4, 8], vec![1, 2, 4, 8, 16, 32, 64]] {
-        variants.push((
-            format!("history {lengths:?}"),
-            Configuration {
-                history_lengths: lengths,
-                ..Configuration::default()
-            },
-        ));
-    }
-    for counter_max in [3, 15, 31] {
-        variants.push((
-            format!("counter max {counter_max}"),
-            Configuration {
-                counter_max,
-                ..Configuration::default()
-            },
-        ));
-    }
-    for base in [1.0, 1.5, 3.0, 4.0] {
-        variants.push((
-            format!("length base {base}"),
-            Configuration {
-                fusion_length_base: base,
-                ..Configuration::default()
-            },
-        ));
-    }
-    for decay in [0.85, 0.9, 0.99] {
-        variants.push((
-            format!("recency decay {decay}"),
-            Configuration {
-                recency_decay: decay,
-                ..Configuration::default()
-            },
-        ));
-    }
-    for interval in [64, 1_024] {
-        variants.push((
-            format!("aging interval {interval}"),
+    report(
+        "ensemble",
+        &eval_tage(
+            &entries,
+            offset,
             Configuration {
-                usefulness_aging_interval: interval,
+                ranking: Ranking::Ensemble,
                 ..Configuration::default()
             },
-        ));
-    }
-    variants
-}
-
-fn grid() -> Vec<(String, Configuration)> {
-    let mut variants = Vec::new();
-    for prior in [0.15, 0.25, 0.4, 0.6, 0.8] {
-        for weekday_mode in [WeekdayMode::Ignored, WeekdayMode::Weekend, WeekdayMode::Exact] {
-            for hour_bucket in [1, 2, 3, 4, 6] {
-                for capacity in [3, 5, 8] {
-                    for time_context_tables in [usize::MAX, 2] {
-                        let configuration = Configuration {
-                            ranking: Ranking::Fusion,
-                            fusion_prior_weight: prior,
-                            weekday_mode,
-                            hour_bucket,
-                            candidate_capacity: capacity,
-                            time_context_tables,
-                            ..Configuration::legacy()
-                        };
-                        let scope = if time_context_tables == usize::MAX {
-                            "all".to_string()
-                        } else {
-                            time_context_tables.to_string()
-                        };
-                        variants.push((
-                            format!(
-                                "prior {prior} wd {weekday_mode:?} hb {hour_bucket} cap {capacity} tct {scope}"
-                            ),
-                            configuration,
-                        ));
-                    }
-                }
-            }
-        }
-    }
-    variants
-}
-
-fn ablations() -> Vec<(String, Configuration)> {
-    let mut variants = Vec::new();
-
-    variants.push(("legacy".to_string(), Configuration::legacy()));
-
-    for size in [1_024, 4_096, 16_384] {
-        variants.push((
-            format!("+ tables {size}"),
-            Configuration {
-                base_table_size: size,
-                tagged_table_size: size,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for bucket in [2, 3, 4, 6, 24] {
-        variants.push((
-            format!("+ hour bucket {bucket}"),
-            Configuration {
-                hour_bucket: bucket,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for (label, mode) in [
-        ("weekend", WeekdayMode::Weekend),
-        ("ignored", WeekdayMode::Ignored),
-    ] {
-        variants.push((
-            format!("+ weekday {label}"),
-            Configuration {
-                weekday_mode: mode,
-                ..Configuration::legacy()
-            },
-        ));
-    }
+            warmup,
+            limit,
+        ),
+    );
 
-    for tables in [0, 1, 2, 3, 4] {
-        variants.push((
-            format!("+ time in first {tables} tables"),
-            Configuration {
-                time_context_tables: tables,
-                ..Configuration::legacy()
-            },
-        ));
+    println!();
+    println!("== cold start, scoring from the first entry ==");
+    let fusion = Configuration {
+        cold_start_entries: 0,
+        ..Configuration::default()
+    };
+    for first in [50, 100, 200, 400, 800] {
+        report(
+            &format!("first {first}: fusion"),
+            &eval_window(&entries, offset, fusion.clone(), 1, first, limit),
+        );
+        report(
+            &format!("first {first}: ensemble"),
+            &eval_window(
+                &entries,
+                offset,
+                Configuration {
+                    ranking: Ranking::Ensemble,
+                    ..fusion.clone()
+                },
+                1,
+                first,
+                limit,
+            ),
+        );
+        report(
+            &format!("first {first}: markov-1 x hour/4"),
+            &eval_baseline(
+                &entries[..first.min(entries.len())],
+                offset,
+                1,
+                limit,
+                MarkovHourRanker::new(4),
+            ),
+        );
     }
 
-    variants.push((
-        "+ elapsed context".to_string(),
-        Configuration {
-            elapsed_context: true,
-            ..Configuration::legacy()
-        },
-    ));
-
-    for lengths in [
-        vec![1, 2, 3, 4],
-        vec![1, 2, 3, 4, 6, 8],
-        vec![1, 2, 4, 8],
-        vec![1, 2, 4, 8, 16, 32, 64],
+    println!();
+    println!("== duration of the entry just started ==");
+    for (label, hour_bucket) in [
+        ("hour bucket 1", 1),
+        ("hour bucket 4 (default)", 4),
+        ("hour bucket 24", 24),
     ] {
-        variants.push((
-            format!("+ history {lengths:?}"),
-            Configuration {
-                history_lengths: lengths,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for capacity in [4, 6, 8, 15] {
-        variants.push((
-            format!("+ candidates {capacity}"),
-            Configuration {
-                candidate_capacity: capacity,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for counter_max in [3, 15, 31] {
-        variants.push((
-            format!("+ counter max {counter_max}"),
-            Configuration {
-                counter_max,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    variants.push((
-        "+ fusion".to_string(),
-        Configuration {
-            ranking: Ranking::Fusion,
-            ..Configuration::legacy()
-        },
-    ));
-
-    for prior in [0.1, 0.25, 0.5, 1.0] {
-        variants.push((
-            format!("+ fusion, prior {prior}"),
-            Configuration {
-                ranking: Ranking::Fusion,
-                fusion_prior_weight: prior,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for base in [1.0, 1.5, 3.0] {
-        variants.push((
-            format!("+ fusion, length base {base}"),
-            Configuration {
-                ranking: Ranking::Fusion,
-                fusion_length_base: base,
-                ..Configuration::legacy()
-            },
-        ));
-    }
-
-    for decay in [0.9, 0.99, 1.0] {
-        variants.push((
-            format!("+ recency decay {decay}"),
-            Configuration {
-                recency_decay: decay,
-                ..Configuration::legacy()
-            },
-        ));
+        report_durations(
+            label,
+            &eval_durations(
+                &entries,
+                offset,
+                Configuration {
+                    hour_bucket,
+                    ..Configuration::default()
+                },
+                warmup,
+            ),
+        );
     }
-
-    variants
 }
@@ -0,0 +1,270 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! How well live suggestions do. The latest ranking `/api/suggest` returned is
+//! remembered, and the entry that follows is scored against it the same way
+//! `examples/eval_predictor.rs` scores a replayed log, so drift shows up without
+//! rerunning the evaluation by hand.
+//!
+//! A challenger configuration can ride along in shadow: its ranking is worked
+//! out for the same calls but never returned, and scored against the same
+//! entries, so the two can be compared before switching.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{constants::STATE_COUNT, predictor::Configuration};
+
+/// The ranking returned for the entry about to be added.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingSuggestion {
+    /// Log length and history version when it was made. Only an entry appended
+    /// at exactly that index, with history untouched since, is scored against it.
+    pub length: u64,
+    pub history_version: u64,
+    /// Best first, at least three long so top-3 can be judged whatever limit
+    /// the client asked for.
+    pub states: Vec<usize>,
+    /// What the challenger would have suggested instead, if one was running.
+    #[serde(default)]
+    pub challenger: Option<ShadowSuggestion>,
+}
+
+impl PendingSuggestion {
+    pub fn score(&self, state: usize, at: i64) -> ScoredTransition {
+        ScoredTransition {
+            state,
+            rank: self.states.iter().position(|&s| s == state),
+            at,
+            challenger: self.challenger.as_ref().map(|shadow| ShadowRank {
+                since: shadow.since,
+                rank: shadow.states.iter().position(|&s| s == state),
+            }),
+        }
+    }
+}
+
+/// A configuration run in shadow next to the live one, set through
+/// `/api/predictor/challenger`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Challenger {
+    pub configuration: Configuration,
+    /// When it was set. Outcomes are tagged with this so a replaced challenger's
+    /// results don't count towards the new one's.
+    pub since: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowSuggestion {
+    pub since: i64,
+    /// Best first, as long as the live ranking it is compared with.
+    pub states: Vec<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowRank {
+    pub since: i64,
+    pub rank: Option<usize>,
+}
+
+/// How one real transition compared with what was suggested before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoredTransition {
+    /// The state actually started.
+    pub state: usize,
+    /// Its position among the suggestions, 0 being first. `None` if it wasn't
+    /// suggested at all.
+    pub rank: Option<usize>,
+    pub at: i64,
+    /// Where the challenger had it, if one was running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenger: Option<ShadowRank>,
+}
+
+/// Rates over a run of outcomes, each a fraction between 0 and 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Accuracy {
+    pub transitions: usize,
+    pub top1: f64,
+    pub top3: f64,
+    /// Mean reciprocal rank, counting a miss as 0.
+    pub mrr: f64,
+}
+
+pub fn accuracy<'a>(outcomes: impl IntoIterator<Item = &'a ScoredTransition>) -> Accuracy {
+    let (mut transitions, mut top1, mut top3, mut reciprocal_rank) = (0usize, 0usize, 0usize, 0.0);
+    for outcome in outcomes {
+        transitions += 1;
+        if let Some(rank) = outcome.rank {
+            top1 += usize::from(rank == 0);
+            top3 += usize::from(rank < 3);
+            reciprocal_rank += 1.0 / (rank as f64 + 1.0);
+        }
+    }
+    if transitions == 0 {
+        return Accuracy::default();
+    }
+    let n = transitions as f64;
+    Accuracy {
+        transitions,
+        top1: top1 as f64 / n,
+        top3: top3 as f64 / n,
+        mrr: reciprocal_rank / n,
+    }
+}
+
+/// [`accuracy`] of the outcomes that ended up in each state.
+pub fn accuracy_by_state(outcomes: &[ScoredTransition]) -> [Accuracy; STATE_COUNT] {
+    std::array::from_fn(|state| accuracy(outcomes.iter().filter(|o| o.state == state)))
+}
+
+/// Paired transitions where exactly one side got it right, and how likely a
+/// split at least that lopsided would be if the two were equally good.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Significance {
+    pub live_only: usize,
+    pub challenger_only: usize,
+    /// Two-sided exact McNemar test. Small values mean the difference is
+    /// unlikely to be chance; 1 when there is nothing to tell them apart.
+    pub p_value: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Comparison {
+    pub live: Accuracy,
+    pub challenger: Accuracy,
+    pub top1: Significance,
+    pub top3: Significance,
+}
+
+/// Live against challenger over the outcomes scored for the challenger set at
+/// `since`, each transition counting for both.
+pub fn compare(outcomes: &[ScoredTransition], since: i64) -> Comparison {
+    let paired: Vec<(&ScoredTransition, ScoredTransition)> = outcomes
+        .iter()
+        .filter_map(|outcome| {
+            let shadow = outcome.challenger.filter(|shadow| shadow.since == since)?;
+            Some((
+                outcome,
+                ScoredTransition {
+                    rank: shadow.rank,
+                    challenger: None,
+                    ..*outcome
+                },
+            ))
+        })
+        .collect();
+    let significance = |within: usize| {
+        let hit = |rank: Option<usize>| rank.is_some_and(|rank| rank < within);
+        let live_only = paired
+            .iter()
+            .filter(|(live, shadow)| hit(live.rank) && !hit(shadow.rank))
+            .count();
+        let challenger_only = paired
+            .iter()
+            .filter(|(live, shadow)| !hit(live.rank) && hit(shadow.rank))
+            .count();
+        Significance {
+            live_only,
+            challenger_only,
+            p_value: mcnemar(live_only, challenger_only),
+        }
+    };
+    Comparison {
+        live: accuracy(paired.iter().map(|(live, _)| *live)),
+        challenger: accuracy(paired.iter().map(|(_, shadow)| shadow)),
+        top1: significance(1),
+        top3: significance(3),
+    }
+}
+
+/// Exact two-sided McNemar p-value: under the null each discordant pair is a
+/// fair coin, so this is twice the binomial tail of the rarer side. Summed in
+/// log space so long runs don't underflow.
+fn mcnemar(b: usize, c: usize) -> f64 {
+    let n = b + c;
+    if n == 0 {
+        return 1.0;
+    }
+    let half = n as f64 * std::f64::consts::LN_2;
+    let mut log_choose = 0.0;
+    let mut tail = (-half).exp();
+    for i in 1..=b.min(c) {
+        log_choose += ((n - i + 1) as f64).ln() - (i as f64).ln();
+        tail += (log_choose - half).exp();
+    }
+    (2.0 * tail).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_ranks_and_rates() {
+        let pending = PendingSuggestion {
+            length: 10,
+            history_version: 0,
+            states: vec![4, 1, 6],
+            challenger: None,
+        };
+        let outcomes = [
+            pending.score(4, 0),
+            pending.score(6, 1),
+            pending.score(2, 2),
+            pending.score(4, 3),
+        ];
+        assert_eq!(outcomes[1].rank, Some(2));
+        assert_eq!(outcomes[2].rank, None);
+
+        let overall = accuracy(&outcomes);
+        assert_eq!(overall.transitions, 4);
+        assert_eq!(overall.top1, 0.5);
+        assert_eq!(overall.top3, 0.75);
+        assert!((overall.mrr - (1.0 + 1.0 / 3.0 + 1.0) / 4.0).abs() < 1e-9);
+
+        let by_state = accuracy_by_state(&outcomes);
+        assert_eq!(by_state[4].top1, 1.0);
+        assert_eq!(by_state[2].top3, 0.0);
+        assert_eq!(by_state[0], Accuracy::default());
+    }
+
+    #[test]
+    fn compares_against_the_current_challenger_only() {
+        let pending = |since, states: Vec<usize>| PendingSuggestion {
+            length: 0,
+            history_version: 0,
+            states: vec![1, 2, 3],
+            challenger: Some(ShadowSuggestion { since, states }),
+        };
+        let mut outcomes = vec![pending(5, vec![1, 2, 3]).score(1, 0)];
+        // Challenger set at 7 ranks 2 first where the live ranking has it second.
+        outcomes.extend((1..=10).map(|at| pending(7, vec![2, 1, 3]).score(2, at)));
+        outcomes.push(pending(7, vec![9, 8, 7]).score(1, 11));
+
+        let comparison = compare(&outcomes, 7);
+        assert_eq!(comparison.live.transitions, 11);
+        assert_eq!(comparison.live.top1, 1.0 / 11.0);
+        assert_eq!(comparison.challenger.top1, 10.0 / 11.0);
+        assert_eq!(comparison.top1.live_only, 1);
+        assert_eq!(comparison.top1.challenger_only, 10);
+        // 2 * (1 + 11) / 2^11
+        assert!((comparison.top1.p_value - 24.0 / 2048.0).abs() < 1e-12);
+        assert_eq!(comparison.top3.live_only, 1);
+        assert_eq!(comparison.top3.challenger_only, 0);
+        assert_eq!(comparison.top3.p_value, 1.0);
+
+        assert_eq!(compare(&outcomes, 3).live.transitions, 0);
+    }
+}
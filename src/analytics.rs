@@ -0,0 +1,1386 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Building blocks shared by the reporting endpoints: turning the event log into
+//! intervals clipped to a window, and turning calendar periods ("last week",
+//! "March 2026") into windows in the user's local time.
+//!
+//! Everything here is pure and works on entries already read from sled, so the
+//! handlers stay in charge of I/O and error responses.
+
+use chrono::{Datelike, Days, Months, NaiveDate, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, iter::Sum};
+
+use crate::{
+    constants::{ALL_STATE_GROUPS, GROUP_COUNT, STATE_COUNT},
+    predictor::{elapsed_bucket, hour_bucket},
+};
+
+/// One stretch of time spent in a single state, `[start, end)` in epoch
+/// milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interval {
+    pub state: usize,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Interval {
+    pub fn length(&self) -> i64 {
+        self.end - self.start
+    }
+}
+
+/// Turns consecutive `(state, start_timestamp)` entries into intervals clipped to
+/// `[from, to)`. Each entry lasts until the next one starts and the latest one
+/// lasts until `now`; time before the first entry is untracked and yields
+/// nothing. `entries` must be ordered by start timestamp, which every write path
+/// enforces.
+pub fn clip_intervals(entries: &[(usize, i64)], from: i64, to: i64, now: i64) -> Vec<Interval> {
+    let mut intervals = Vec::new();
+
+    // Skip straight to the entry that is running at `from`.
+    let first = entries
+        .partition_point(|&(_, start)| start <= from)
+        .saturating_sub(1);
+
+    for (i, &(state, start)) in entries.iter().enumerate().skip(first) {
+        if start >= to {
+            break;
+        }
+        let end = entries.get(i + 1).map_or(now, |&(_, next)| next);
+        let start = start.max(from);
+        let end = end.min(to);
+        if start < end {
+            intervals.push(Interval { state, start, end });
+        }
+    }
+
+    intervals
+}
+
+/// Milliseconds spent in each state across `intervals`.
+pub fn state_totals(intervals: &[Interval]) -> [i64; STATE_COUNT] {
+    let mut totals = [0i64; STATE_COUNT];
+    for interval in intervals {
+        totals[interval.state] += interval.length();
+    }
+    totals
+}
+
+/// Whether totals are also reported per state group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    State,
+    Group,
+}
+
+/// Adds per-state `totals` up into [`ALL_STATE_GROUPS`].
+pub fn group_totals<T: Copy + Sum>(totals: &[T; STATE_COUNT]) -> [T; GROUP_COUNT] {
+    ALL_STATE_GROUPS.map(|group| group.states.iter().map(|&state| totals[state]).sum())
+}
+
+/// Adds a state × state matrix of counts up into group × group, so
+/// `grouped[a][b]` counts every pair from a state of group `a` to one of group
+/// `b`. Moves between two states of the same group land on the diagonal.
+pub fn group_counts(
+    counts: &[[u64; STATE_COUNT]; STATE_COUNT],
+) -> [[u64; GROUP_COUNT]; GROUP_COUNT] {
+    ALL_STATE_GROUPS.map(|from| {
+        ALL_STATE_GROUPS.map(|to| {
+            from.states
+                .iter()
+                .flat_map(|&a| to.states.iter().map(move |&b| counts[a][b]))
+                .sum()
+        })
+    })
+}
+
+/// A calendar unit that windows can be aligned to. Weeks start on Monday.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// A labelled `[from, to)` window in epoch milliseconds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Window {
+    pub from: i64,
+    pub to: i64,
+    pub label: String,
+}
+
+/// The calendar `period` containing `now` in `tz`, shifted by `offset` whole
+/// periods (0 is the current one, -1 the one before). `None` if the result
+/// falls outside what chrono can represent.
+pub fn calendar_window<Z: TimeZone>(
+    tz: &Z,
+    period: Period,
+    offset: i32,
+    now: i64,
+) -> Option<Window> {
+    let today = local_date(tz, now)?;
+    let start = shift_period(period_start(today, period), period, i64::from(offset))?;
+    period_window(tz, start, period)
+}
+
+/// The `period` starting on local date `start`, which must already be aligned.
+fn period_window<Z: TimeZone>(tz: &Z, start: NaiveDate, period: Period) -> Option<Window> {
+    let end = shift_period(start, period, 1)?;
+
+    Some(Window {
+        from: local_midnight(tz, start)?,
+        to: local_midnight(tz, end)?,
+        label: period_label(start, period),
+    })
+}
+
+/// Consecutive `period`-long windows covering `[from, to)`, widened to whole
+/// periods in `tz`. Boundaries fall on local midnights, so across a DST change a
+/// day window is 23 or 25 hours long. `None` if more than `max_windows` would be
+/// needed.
+pub fn period_windows<Z: TimeZone>(
+    tz: &Z,
+    from: i64,
+    to: i64,
+    period: Period,
+    max_windows: usize,
+) -> Option<Vec<Window>> {
+    let mut start = period_start(local_date(tz, from)?, period);
+    let mut start_at = local_midnight(tz, start)?;
+    let mut windows = Vec::new();
+
+    while start_at < to {
+        if windows.len() == max_windows {
+            return None;
+        }
+        let end = shift_period(start, period, 1)?;
+        let end_at = local_midnight(tz, end)?;
+        windows.push(Window {
+            from: start_at,
+            to: end_at,
+            label: period_label(start, period),
+        });
+        start = end;
+        start_at = end_at;
+    }
+
+    Some(windows)
+}
+
+/// Per-state totals for each of `windows`, which must be ordered and must not
+/// overlap. Intervals crossing a boundary are split between the windows.
+pub fn window_totals(intervals: &[Interval], windows: &[Window]) -> Vec<[i64; STATE_COUNT]> {
+    let mut totals = vec![[0i64; STATE_COUNT]; windows.len()];
+    let mut first = 0;
+
+    for interval in intervals {
+        // Intervals are ordered too, so windows wholly before this one are done.
+        while first < windows.len() && windows[first].to <= interval.start {
+            first += 1;
+        }
+        for (window, total) in windows[first..].iter().zip(&mut totals[first..]) {
+            if window.from >= interval.end {
+                break;
+            }
+            let overlap = interval.end.min(window.to) - interval.start.max(window.from);
+            if overlap > 0 {
+                total[interval.state] += overlap;
+            }
+        }
+    }
+
+    totals
+}
+
+/// What a calendar window is compared against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Baseline {
+    /// The period just before.
+    Previous,
+    /// The mean of the four periods before.
+    Avg4,
+    /// The same period a year earlier. For weeks that is 52 weeks back, so
+    /// weekdays line up.
+    SameLastYear,
+}
+
+/// The windows `baseline` is taken over for the calendar `period` starting on
+/// local date `start`, oldest first. `None` if any falls outside what chrono
+/// can represent.
+pub fn baseline_windows<Z: TimeZone>(
+    tz: &Z,
+    start: NaiveDate,
+    period: Period,
+    baseline: Baseline,
+) -> Option<Vec<Window>> {
+    let starts = match baseline {
+        Baseline::Previous => vec![shift_period(start, period, -1)?],
+        Baseline::Avg4 => (1..=4)
+            .rev()
+            .map(|back| shift_period(start, period, -back))
+            .collect::<Option<_>>()?,
+        Baseline::SameLastYear => vec![match period {
+            Period::Day => start.checked_sub_months(Months::new(12))?,
+            Period::Week => shift_period(start, period, -52)?,
+            Period::Month => shift_period(start, period, -12)?,
+            Period::Year => shift_period(start, period, -1)?,
+        }],
+    };
+
+    starts
+        .into_iter()
+        .map(|start| period_window(tz, start, period))
+        .collect()
+}
+
+/// Spreads `intervals` over a weekday × time-of-day grid in `tz`, counting only
+/// `states`. Rows are weekdays, Monday first; columns are `bucket_minutes`-wide
+/// slices of the local day, which must divide it evenly. Cells hold
+/// milliseconds.
+pub fn weekday_heatmap<Z: TimeZone>(
+    tz: &Z,
+    intervals: &[Interval],
+    states: &[usize],
+    bucket_minutes: u32,
+) -> Vec<Vec<i64>> {
+    const DAY_MS: i64 = 24 * 3600 * 1000;
+    let bucket_ms = i64::from(bucket_minutes.max(1)) * 60_000;
+    let columns = (DAY_MS / bucket_ms) as usize;
+    let mut grid = vec![vec![0i64; columns]; 7];
+
+    for interval in intervals.iter().filter(|i| states.contains(&i.state)) {
+        let mut cursor = interval.start;
+        while cursor < interval.end {
+            let Some(local) = tz.timestamp_millis_opt(cursor).single() else {
+                break;
+            };
+            let local = local.naive_local();
+            let into_day = i64::from(local.num_seconds_from_midnight()) * 1000
+                + i64::from(local.nanosecond() / 1_000_000 % 1000);
+            let column = ((into_day / bucket_ms) as usize).min(columns - 1);
+            // Re-derive the local time after every slice rather than stepping by
+            // a fixed width, so a DST change mid-interval lands in the right cell.
+            let slice_end = (cursor + bucket_ms * (column as i64 + 1) - into_day)
+                .min(interval.end)
+                .max(cursor + 1);
+            let row = local.weekday().num_days_from_monday() as usize;
+            grid[row][column] += slice_end - cursor;
+            cursor = slice_end;
+        }
+    }
+
+    grid
+}
+
+/// Restricts a statistic to moments in one hour block and/or on one weekday,
+/// both in local time. Hour blocks are counted the way the predictor counts
+/// them, see [`hour_bucket`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeFilter {
+    /// Width of an hour block; 4 splits the day into six.
+    pub hour_bucket: u32,
+    /// Which block to keep, 0 being the one starting at midnight.
+    pub hour: Option<u32>,
+    /// Which weekday to keep, 0 being Monday.
+    pub weekday: Option<u32>,
+}
+
+impl TimeFilter {
+    pub fn matches<Z: TimeZone>(&self, tz: &Z, at: i64) -> bool {
+        let Some(local) = tz.timestamp_millis_opt(at).single() else {
+            return false;
+        };
+        self.hour
+            .is_none_or(|h| hour_bucket(local.hour(), self.hour_bucket) == h)
+            && self
+                .weekday
+                .is_none_or(|w| local.weekday().num_days_from_monday() == w)
+    }
+}
+
+/// A local-time attribute to break a statistic down by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    /// 0 is Monday.
+    Weekday,
+    /// The `hour_bucket`-wide hour block, as in [`TimeFilter`].
+    Hour,
+}
+
+impl SplitBy {
+    pub fn key<Z: TimeZone>(&self, tz: &Z, at: i64, hour_bucket_width: u32) -> u32 {
+        let Some(local) = tz.timestamp_millis_opt(at).single() else {
+            return 0;
+        };
+        match self {
+            SplitBy::Weekday => local.weekday().num_days_from_monday(),
+            SplitBy::Hour => hour_bucket(local.hour(), hour_bucket_width),
+        }
+    }
+}
+
+/// Order-1 transition counts and per-state dwell times.
+#[derive(Clone, Debug)]
+pub struct TransitionStats {
+    /// `counts[a][b]` is how often `b` directly followed `a`.
+    pub counts: [[u64; STATE_COUNT]; STATE_COUNT],
+    /// How many sessions of each state started and have since ended, and their
+    /// total length. The entry still running is left out, since cutting it off
+    /// at `now` would pull the mean down.
+    pub sessions: [u64; STATE_COUNT],
+    pub dwell: [i64; STATE_COUNT],
+}
+
+/// Counts the transitions that happened in `[from, to)` and passed `filter`,
+/// judged by when the new state started. Dwell times are over finished sessions
+/// that started in the window and passed `filter`, and run to the next entry,
+/// even past `to`. Repeated entries of the same state are not counted as
+/// transitions.
+pub fn transition_stats<Z: TimeZone>(
+    tz: &Z,
+    entries: &[(usize, i64)],
+    from: i64,
+    to: i64,
+    filter: &TimeFilter,
+) -> TransitionStats {
+    let mut stats = TransitionStats {
+        counts: [[0; STATE_COUNT]; STATE_COUNT],
+        sessions: [0; STATE_COUNT],
+        dwell: [0; STATE_COUNT],
+    };
+
+    let first = entries.partition_point(|&(_, start)| start < from);
+    for (i, &(state, start)) in entries.iter().enumerate().skip(first) {
+        if start >= to {
+            break;
+        }
+        if !filter.matches(tz, start) {
+            continue;
+        }
+
+        if let Some(&(_, end)) = entries.get(i + 1) {
+            stats.sessions[state] += 1;
+            stats.dwell[state] += (end - start).max(0);
+        }
+
+        if let Some(&(previous, _)) = i.checked_sub(1).and_then(|p| entries.get(p))
+            && previous != state
+        {
+            stats.counts[previous][state] += 1;
+        }
+    }
+
+    stats
+}
+
+/// Every session that started in `[from, to)`, at its full length: it runs to
+/// the next entry, or to `now` if it is still going, even past `to`. See
+/// [`finished_sessions_starting_in`] to leave the running one out.
+pub fn sessions_starting_in(
+    entries: &[(usize, i64)],
+    from: i64,
+    to: i64,
+    now: i64,
+) -> Vec<Interval> {
+    let first = entries.partition_point(|&(_, start)| start < from);
+    entries
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take_while(|&(_, &(_, start))| start < to)
+        .map(|(i, &(state, start))| Interval {
+            state,
+            start,
+            end: entries.get(i + 1).map_or(now, |&(_, next)| next).max(start),
+        })
+        .collect()
+}
+
+/// Like [`sessions_starting_in`], but without the entry still running, whose
+/// length isn't known yet.
+pub fn finished_sessions_starting_in(
+    entries: &[(usize, i64)],
+    from: i64,
+    to: i64,
+) -> Vec<Interval> {
+    let Some((_, finished)) = entries.split_last() else {
+        return Vec::new();
+    };
+    let first = finished.partition_point(|&(_, start)| start < from);
+    finished
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take_while(|&(_, &(_, start))| start < to)
+        .map(|(i, &(state, start))| Interval {
+            state,
+            start,
+            end: entries[i + 1].1.max(start),
+        })
+        .collect()
+}
+
+/// Labels for [`Distribution::histogram`], which buckets session lengths the way
+/// the predictor buckets elapsed time (see [`elapsed_bucket`]).
+pub const HISTOGRAM_BINS: [&str; 9] = [
+    "0m", "<5m", "5-15m", "15-30m", "30-60m", "1-2h", "2-4h", "4-8h", "8h+",
+];
+
+/// Summary statistics over a set of durations, in milliseconds. Percentiles use
+/// the nearest-rank method, so they are always an observed length.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub total: i64,
+    pub mean: i64,
+    pub median: i64,
+    pub p10: i64,
+    pub p90: i64,
+    pub longest: i64,
+    pub histogram: [u64; HISTOGRAM_BINS.len()],
+}
+
+impl Distribution {
+    /// `None` for an empty set, which has no meaningful statistics.
+    pub fn of(durations: &[i64]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        let mut sorted = durations.to_vec();
+        sorted.sort_unstable();
+
+        let mut histogram = [0u64; HISTOGRAM_BINS.len()];
+        for &duration in &sorted {
+            histogram[elapsed_bucket(duration) as usize] += 1;
+        }
+
+        let total = sorted.iter().sum::<i64>();
+        Some(Self {
+            count: sorted.len(),
+            total,
+            mean: total / sorted.len() as i64,
+            median: percentile(&sorted, 0.5),
+            p10: percentile(&sorted, 0.1),
+            p90: percentile(&sorted, 0.9),
+            longest: sorted[sorted.len() - 1],
+            histogram,
+        })
+    }
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+pub fn percentile(sorted: &[i64], fraction: f64) -> i64 {
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// How scattered one day was.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DayFocus {
+    pub from: i64,
+    pub to: i64,
+    pub label: String,
+    /// Entries that started this day and changed the state.
+    pub switches: u64,
+    /// Runs of back-to-back focus sessions that started this day, and their
+    /// full length. Study straight into Work is one block, not two.
+    pub focus_blocks: u64,
+    pub focus_time: i64,
+    /// Mean uninterrupted focus block, `None` on a day without one.
+    pub mean_focus_block: Option<i64>,
+    /// Time this day spent in sessions, of any state, shorter than the cut-off.
+    pub short_block_time: i64,
+    pub tracked: i64,
+}
+
+/// Fragmentation metrics for each of `days`, which must be ordered and must not
+/// overlap. A session counts as short by its full length, even where it spills
+/// over into a neighbouring day.
+pub fn focus_by_day(
+    entries: &[(usize, i64)],
+    days: &[Window],
+    now: i64,
+    focus_states: &[usize],
+    short_block: i64,
+) -> Vec<DayFocus> {
+    let Some(last) = days.last() else {
+        return Vec::new();
+    };
+    let sessions = sessions_starting_in(entries, i64::MIN, last.to, now);
+    let mut blocks: Vec<Interval> = Vec::new();
+    for session in sessions.iter().filter(|s| focus_states.contains(&s.state)) {
+        match blocks.last_mut() {
+            Some(block) if block.end == session.start => block.end = session.end,
+            _ => blocks.push(*session),
+        }
+    }
+    let (mut first, mut first_block) = (0, 0);
+
+    days.iter()
+        .map(|day| {
+            let mut focus = DayFocus {
+                from: day.from,
+                to: day.to,
+                label: day.label.clone(),
+                switches: 0,
+                focus_blocks: 0,
+                focus_time: 0,
+                mean_focus_block: None,
+                short_block_time: 0,
+                tracked: 0,
+            };
+
+            while first < sessions.len() && sessions[first].end <= day.from {
+                first += 1;
+            }
+            for (i, session) in sessions.iter().enumerate().skip(first) {
+                if session.start >= day.to {
+                    break;
+                }
+                let overlap = session.end.min(day.to) - session.start.max(day.from);
+                if overlap <= 0 {
+                    continue;
+                }
+                focus.tracked += overlap;
+                if session.length() < short_block {
+                    focus.short_block_time += overlap;
+                }
+
+                if session.start >= day.from && i > 0 && sessions[i - 1].state != session.state {
+                    focus.switches += 1;
+                }
+            }
+
+            while first_block < blocks.len() && blocks[first_block].start < day.from {
+                first_block += 1;
+            }
+            for block in blocks[first_block..]
+                .iter()
+                .take_while(|block| block.start < day.to)
+            {
+                focus.focus_blocks += 1;
+                focus.focus_time += block.length();
+            }
+
+            focus.mean_focus_block =
+                (focus.focus_blocks > 0).then(|| focus.focus_time / focus.focus_blocks as i64);
+            focus
+        })
+        .collect()
+}
+
+/// A stretch of sleep, possibly broken by short wake-ups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SleepEpisode {
+    pub start: i64,
+    pub end: i64,
+    /// Time actually asleep, i.e. the length minus the wake-ups.
+    pub asleep: i64,
+    pub interruptions: u32,
+}
+
+/// Sleep sessions of `sleep_state` that ended in `[from, to)`, with sessions
+/// separated by at most `max_gap` of anything else merged into one episode.
+/// Sleep still running at `now` is left out, since it has no wake time yet.
+pub fn sleep_episodes(
+    entries: &[(usize, i64)],
+    sleep_state: usize,
+    from: i64,
+    to: i64,
+    now: i64,
+    max_gap: i64,
+) -> Vec<SleepEpisode> {
+    let mut episodes: Vec<SleepEpisode> = Vec::new();
+
+    for session in sessions_starting_in(entries, i64::MIN, to, now) {
+        if session.state != sleep_state || session.end >= now {
+            continue;
+        }
+        match episodes.last_mut() {
+            Some(last) if session.start - last.end <= max_gap => {
+                last.end = session.end;
+                last.asleep += session.length();
+                last.interruptions += 1;
+            }
+            _ => episodes.push(SleepEpisode {
+                start: session.start,
+                end: session.end,
+                asleep: session.length(),
+                interruptions: 0,
+            }),
+        }
+    }
+
+    episodes.retain(|episode| episode.end >= from && episode.end < to);
+    episodes
+}
+
+/// One night, keyed by the local date of waking up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Night {
+    pub date: NaiveDate,
+    /// The longest episode that ended on `date`.
+    pub main: SleepEpisode,
+    /// Every other episode that ended on `date`.
+    pub naps: Vec<SleepEpisode>,
+}
+
+/// Groups `episodes` into nights by the local date they ended on, taking the
+/// longest as the main sleep and the rest as naps.
+pub fn group_nights<Z: TimeZone>(tz: &Z, episodes: &[SleepEpisode]) -> Vec<Night> {
+    let mut by_date: BTreeMap<NaiveDate, Vec<SleepEpisode>> = BTreeMap::new();
+    for episode in episodes {
+        if let Some(date) = local_date(tz, episode.end) {
+            by_date.entry(date).or_default().push(*episode);
+        }
+    }
+
+    by_date
+        .into_iter()
+        .map(|(date, mut episodes)| {
+            let longest = episodes
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, e)| e.asleep)
+                .map_or(0, |(i, _)| i);
+            let main = episodes.remove(longest);
+            Night {
+                date,
+                main,
+                naps: episodes,
+            }
+        })
+        .collect()
+}
+
+/// The Sleep Regularity Index over `[from, to)`: how likely the user is to be in
+/// the same sleep/wake state at two moments 24 hours apart, scaled so that 100
+/// is perfectly regular and 0 is chance. Sampled every `step` milliseconds.
+/// `None` if the window is shorter than a day.
+pub fn sleep_regularity_index(sleep: &[Interval], from: i64, to: i64, step: i64) -> Option<f64> {
+    const DAY_MS: i64 = 24 * 3600 * 1000;
+    let asleep = |at: i64| {
+        let after = sleep.partition_point(|interval| interval.start <= at);
+        after > 0 && sleep[after - 1].end > at
+    };
+
+    let mut samples = 0u64;
+    let mut matching = 0u64;
+    let mut at = from;
+    while at + DAY_MS < to {
+        samples += 1;
+        if asleep(at) == asleep(at + DAY_MS) {
+            matching += 1;
+        }
+        at += step.max(1);
+    }
+
+    (samples > 0).then(|| 200.0 * matching as f64 / samples as f64 - 100.0)
+}
+
+/// Local time of day in minutes after midnight.
+pub fn minutes_into_day<Z: TimeZone>(tz: &Z, at: i64) -> Option<u32> {
+    let local = tz.timestamp_millis_opt(at).single()?;
+    Some(local.hour() * 60 + local.minute())
+}
+
+/// Circular mean of times of day given as minutes after midnight, so that 23:30
+/// and 00:30 average to midnight rather than noon.
+pub fn mean_time_of_day(minutes: &[u32]) -> Option<u32> {
+    if minutes.is_empty() {
+        return None;
+    }
+    let (sin, cos) = minutes.iter().fold((0.0, 0.0), |(sin, cos), &m| {
+        let angle = f64::from(m) / 1440.0 * std::f64::consts::TAU;
+        (sin + angle.sin(), cos + angle.cos())
+    });
+    let angle = sin.atan2(cos).rem_euclid(std::f64::consts::TAU);
+    Some((angle / std::f64::consts::TAU * 1440.0).round() as u32 % 1440)
+}
+
+/// Everything a year in review is built from, gathered in one pass.
+#[derive(Clone, Debug, PartialEq)]
+pub struct YearTally {
+    /// Per-state totals for each of the day windows passed in.
+    pub days: Vec<[i64; STATE_COUNT]>,
+    /// How often each `(from, to)` state change happened.
+    pub transitions: BTreeMap<(usize, usize), u32>,
+    /// The longest session of each state, clipped to the days.
+    pub longest: [Option<Interval>; STATE_COUNT],
+}
+
+/// Walks `entries` once over ordered, contiguous day `windows`, splitting time
+/// between days and noting state changes and record sessions on the way.
+pub fn tally_year(entries: &[(usize, i64)], days: &[Window], now: i64) -> YearTally {
+    let mut tally = YearTally {
+        days: vec![[0i64; STATE_COUNT]; days.len()],
+        transitions: BTreeMap::new(),
+        longest: [None; STATE_COUNT],
+    };
+    let (Some(first_day), Some(last_day)) = (days.first(), days.last()) else {
+        return tally;
+    };
+    let (from, to) = (first_day.from, last_day.to);
+
+    let first = entries
+        .partition_point(|&(_, start)| start <= from)
+        .saturating_sub(1);
+    let mut day = 0;
+
+    for (i, &(state, start)) in entries.iter().enumerate().skip(first) {
+        if start >= to {
+            break;
+        }
+        if start >= from
+            && let Some(&(previous, _)) = i.checked_sub(1).and_then(|p| entries.get(p))
+            && previous != state
+        {
+            *tally.transitions.entry((previous, state)).or_default() += 1;
+        }
+
+        let end = entries.get(i + 1).map_or(now, |&(_, next)| next).min(to);
+        let start = start.max(from);
+        if start >= end {
+            continue;
+        }
+
+        let session = Interval { state, start, end };
+        if tally.longest[state].is_none_or(|longest| session.length() > longest.length()) {
+            tally.longest[state] = Some(session);
+        }
+
+        while day < days.len() && days[day].to <= start {
+            day += 1;
+        }
+        for (window, totals) in days[day..].iter().zip(&mut tally.days[day..]) {
+            if window.from >= end {
+                break;
+            }
+            totals[state] += end.min(window.to) - start.max(window.from);
+        }
+    }
+
+    tally
+}
+
+/// What a typical day on one weekday looks like, per state (or per group, for
+/// `N = GROUP_COUNT`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DayNorm<const N: usize = STATE_COUNT> {
+    pub median: [i64; N],
+    /// Median absolute deviation from `median`.
+    pub mad: [i64; N],
+    /// How many days the norm was taken over.
+    pub samples: usize,
+}
+
+/// Norms per weekday (Monday first) from `(weekday, totals)` pairs of past days.
+/// Weekdays with fewer than `min_samples` days have no norm.
+pub fn weekday_norms<const N: usize>(
+    days: &[(u32, [i64; N])],
+    min_samples: usize,
+) -> [Option<DayNorm<N>>; 7] {
+    std::array::from_fn(|weekday| {
+        let totals: Vec<&[i64; N]> = days
+            .iter()
+            .filter(|(w, _)| *w as usize == weekday)
+            .map(|(_, totals)| totals)
+            .collect();
+        if totals.len() < min_samples.max(1) {
+            return None;
+        }
+
+        let mut median = [0i64; N];
+        let mut mad = [0i64; N];
+        for state in 0..N {
+            let mut values: Vec<i64> = totals.iter().map(|t| t[state]).collect();
+            values.sort_unstable();
+            median[state] = percentile(&values, 0.5);
+            let mut spread: Vec<i64> = values.iter().map(|v| (v - median[state]).abs()).collect();
+            spread.sort_unstable();
+            mad[state] = percentile(&spread, 0.5);
+        }
+
+        Some(DayNorm {
+            median,
+            mad,
+            samples: totals.len(),
+        })
+    })
+}
+
+/// One state's departure from the norm on a given day.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Deviation {
+    pub state: usize,
+    pub total: i64,
+    pub median: i64,
+    /// Robust z-score: distance from the median in MAD-based standard
+    /// deviations. Negative when below the norm.
+    pub score: f64,
+}
+
+/// Scales a MAD to a standard deviation for normally distributed data.
+const MAD_TO_SIGMA: f64 = 1.4826;
+
+/// The states whose `totals` are at least `threshold` robust standard
+/// deviations away from `norm`, largest first. The MAD is floored at
+/// `min_scale` milliseconds so that a state that never varies (say, no
+/// Emergency at all) isn't flagged for a few minutes.
+pub fn deviations<const N: usize>(
+    totals: &[i64; N],
+    norm: &DayNorm<N>,
+    min_scale: i64,
+    threshold: f64,
+) -> Vec<Deviation> {
+    let mut found: Vec<Deviation> = (0..N)
+        .filter_map(|state| {
+            let scale = MAD_TO_SIGMA * norm.mad[state].max(min_scale).max(1) as f64;
+            let score = (totals[state] - norm.median[state]) as f64 / scale;
+            (score.abs() >= threshold).then_some(Deviation {
+                state,
+                total: totals[state],
+                median: norm.median[state],
+                score,
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| b.score.abs().total_cmp(&a.score.abs()));
+    found
+}
+
+/// How a period starting on `start` is labelled: `2026-03-14`, `2026-W11`,
+/// `2026-03` or `2026`.
+pub fn period_label(start: NaiveDate, period: Period) -> String {
+    match period {
+        Period::Day => start.format("%Y-%m-%d").to_string(),
+        Period::Week => {
+            let week = start.iso_week();
+            format!("{}-W{:02}", week.year(), week.week())
+        }
+        Period::Month => start.format("%Y-%m").to_string(),
+        Period::Year => start.format("%Y").to_string(),
+    }
+}
+
+/// An arbitrary `[from, to)` window, labelled with its local start and end.
+pub fn explicit_window<Z: TimeZone>(tz: &Z, from: i64, to: i64) -> Option<Window> {
+    let format = |at: i64| {
+        tz.timestamp_millis_opt(at)
+            .single()
+            .map(|t| t.naive_local().format("%Y-%m-%d %H:%M").to_string())
+    };
+    Some(Window {
+        from,
+        to,
+        label: format!("{} – {}", format(from)?, format(to)?),
+    })
+}
+
+/// The first day of the `period` that contains `date`.
+pub fn period_start(date: NaiveDate, period: Period) -> NaiveDate {
+    match period {
+        Period::Day => date,
+        Period::Week => date - Days::new(u64::from(date.weekday().num_days_from_monday())),
+        Period::Month => date.with_day(1).unwrap(),
+        Period::Year => date.with_ordinal(1).unwrap(),
+    }
+}
+
+/// Moves a period-aligned `start` by `count` whole periods.
+pub fn shift_period(start: NaiveDate, period: Period, count: i64) -> Option<NaiveDate> {
+    let magnitude = count.unsigned_abs();
+    match period {
+        Period::Day | Period::Week => {
+            let days = Days::new(if period == Period::Week {
+                magnitude.checked_mul(7)?
+            } else {
+                magnitude
+            });
+            if count >= 0 {
+                start.checked_add_days(days)
+            } else {
+                start.checked_sub_days(days)
+            }
+        }
+        Period::Month | Period::Year => {
+            let months = if period == Period::Year {
+                magnitude.checked_mul(12)?
+            } else {
+                magnitude
+            };
+            let months = Months::new(u32::try_from(months).ok()?);
+            if count >= 0 {
+                start.checked_add_months(months)
+            } else {
+                start.checked_sub_months(months)
+            }
+        }
+    }
+}
+
+/// The local calendar date of `at` in `tz`.
+pub fn local_date<Z: TimeZone>(tz: &Z, at: i64) -> Option<NaiveDate> {
+    tz.timestamp_millis_opt(at)
+        .single()
+        .map(|t| t.naive_local().date())
+}
+
+/// The first instant of `date` in `tz`. Where a DST change skips midnight, the
+/// day starts at the first local hour that exists.
+pub fn local_midnight<Z: TimeZone>(tz: &Z, date: NaiveDate) -> Option<i64> {
+    (0..24).find_map(|hour| {
+        tz.from_local_datetime(&date.and_hms_opt(hour, 0, 0)?)
+            .earliest()
+            .map(|t| t.timestamp_millis())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::{America::Sao_Paulo, Europe::London, UTC};
+
+    const HOUR: i64 = 3_600_000;
+
+    fn at(tz: &impl TimeZone, y: i32, m: u32, d: u32, h: u32) -> i64 {
+        tz.with_ymd_and_hms(y, m, d, h, 0, 0)
+            .earliest()
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn clips_intervals_to_window() {
+        let entries = [(0, 0), (1, 10 * HOUR), (2, 20 * HOUR)];
+        let intervals = clip_intervals(&entries, 5 * HOUR, 25 * HOUR, 30 * HOUR);
+        assert_eq!(
+            intervals,
+            vec![
+                Interval {
+                    state: 0,
+                    start: 5 * HOUR,
+                    end: 10 * HOUR
+                },
+                Interval {
+                    state: 1,
+                    start: 10 * HOUR,
+                    end: 20 * HOUR
+                },
+                Interval {
+                    state: 2,
+                    start: 20 * HOUR,
+                    end: 25 * HOUR
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn latest_entry_runs_until_now() {
+        let entries = [(3, 0), (4, 10 * HOUR)];
+        let totals = state_totals(&clip_intervals(&entries, 0, 100 * HOUR, 12 * HOUR));
+        assert_eq!(totals[3], 10 * HOUR);
+        assert_eq!(totals[4], 2 * HOUR);
+    }
+
+    #[test]
+    fn time_before_first_entry_is_untracked() {
+        let entries = [(1, 10 * HOUR)];
+        let intervals = clip_intervals(&entries, 0, 20 * HOUR, 20 * HOUR);
+        assert_eq!(state_totals(&intervals)[1], 10 * HOUR);
+        assert_eq!(intervals.len(), 1);
+    }
+
+    #[test]
+    fn calendar_windows_align_to_local_time() {
+        // Wednesday 2026-03-18, 10:00 UTC.
+        let now = at(&UTC, 2026, 3, 18, 10);
+
+        let yesterday = calendar_window(&UTC, Period::Day, -1, now).unwrap();
+        assert_eq!(yesterday.label, "2026-03-17");
+        assert_eq!(yesterday.from, at(&UTC, 2026, 3, 17, 0));
+        assert_eq!(yesterday.to, at(&UTC, 2026, 3, 18, 0));
+
+        let last_week = calendar_window(&UTC, Period::Week, -1, now).unwrap();
+        assert_eq!(last_week.label, "2026-W11");
+        assert_eq!(last_week.from, at(&UTC, 2026, 3, 9, 0));
+
+        let march = calendar_window(&UTC, Period::Month, 0, now).unwrap();
+        assert_eq!(march.label, "2026-03");
+        assert_eq!(march.to, at(&UTC, 2026, 4, 1, 0));
+
+        let last_year = calendar_window(&UTC, Period::Year, -1, now).unwrap();
+        assert_eq!(last_year.label, "2025");
+    }
+
+    #[test]
+    fn baselines_line_up_with_the_current_period() {
+        // Week 2026-W12 starts on Monday 2026-03-16.
+        let start = NaiveDate::from_ymd_opt(2026, 3, 16).unwrap();
+        let labels = |baseline| -> Vec<String> {
+            baseline_windows(&UTC, start, Period::Week, baseline)
+                .unwrap()
+                .into_iter()
+                .map(|w| w.label)
+                .collect()
+        };
+        assert_eq!(labels(Baseline::Previous), ["2026-W11"]);
+        assert_eq!(
+            labels(Baseline::Avg4),
+            ["2026-W08", "2026-W09", "2026-W10", "2026-W11"]
+        );
+        let last_year = baseline_windows(&UTC, start, Period::Week, Baseline::SameLastYear);
+        let last_year = last_year.unwrap();
+        assert_eq!(last_year[0].from, at(&UTC, 2025, 3, 17, 0));
+        assert_eq!(last_year[0].label, "2025-W12");
+
+        let leap_day = NaiveDate::from_ymd_opt(2028, 2, 29).unwrap();
+        let last_year = baseline_windows(&UTC, leap_day, Period::Day, Baseline::SameLastYear);
+        assert_eq!(last_year.unwrap()[0].label, "2027-02-28");
+    }
+
+    #[test]
+    fn flags_days_far_from_their_weekday_norm() {
+        // Eight Mondays of 8h Sleep (give or take 20 minutes) and 8h Work.
+        let mut history = Vec::new();
+        for week in 0..8 {
+            let mut totals = [0i64; STATE_COUNT];
+            totals[7] = 8 * HOUR + (week % 3 - 1) * 20 * 60_000;
+            totals[1] = 8 * HOUR;
+            history.push((0, totals));
+        }
+        // A single Tuesday doesn't make a norm.
+        history.push((1, [0; STATE_COUNT]));
+
+        let norms = weekday_norms(&history, 4);
+        assert!(norms[1].is_none());
+        let monday = norms[0].as_ref().unwrap();
+        assert_eq!(monday.samples, 8);
+        assert_eq!(monday.median[7], 8 * HOUR);
+
+        let mut today = [0i64; STATE_COUNT];
+        today[7] = 4 * HOUR;
+        today[1] = 8 * HOUR;
+        today[14] = 10 * 60_000;
+        let found = deviations(&today, monday, 15 * 60_000, 3.0);
+        // Ten minutes of Emergency is within the floor; four hours short on
+        // Sleep is not.
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].state, 7);
+        assert!(found[0].score < -3.0);
+    }
+
+    #[test]
+    fn tallies_a_year_in_one_pass() {
+        let days = period_windows(&UTC, 0, 3 * 24 * HOUR, Period::Day, 10).unwrap();
+        // Sleep across the first midnight, Work twice, then Sleep again until now.
+        let entries = [
+            (7, -2 * HOUR),
+            (1, 8 * HOUR),
+            (0, 30 * HOUR),
+            (1, 32 * HOUR),
+            (7, 46 * HOUR),
+        ];
+        let tally = tally_year(&entries, &days, 60 * HOUR);
+
+        assert_eq!(tally.days[0][7], 8 * HOUR);
+        assert_eq!(tally.days[0][1], 16 * HOUR);
+        assert_eq!(tally.days[1][1], 6 * HOUR + 14 * HOUR);
+        assert_eq!(tally.days[1][7], 2 * HOUR);
+        assert_eq!(tally.days[2][7], 12 * HOUR);
+        // The Sleep that started before the first day isn't a transition in it.
+        assert_eq!(tally.transitions.get(&(7, 1)), Some(&1));
+        assert_eq!(tally.transitions.get(&(1, 0)), Some(&1));
+        assert_eq!(tally.transitions.values().sum::<u32>(), 4);
+        assert_eq!(tally.longest[1].unwrap().length(), 22 * HOUR);
+        assert_eq!(tally.longest[7].unwrap().length(), 14 * HOUR);
+    }
+
+    #[test]
+    fn every_state_is_in_exactly_one_group() {
+        let mut seen = [0; STATE_COUNT];
+        for group in ALL_STATE_GROUPS {
+            for &state in group.states {
+                seen[state] += 1;
+            }
+        }
+        assert_eq!(seen, [1; STATE_COUNT]);
+
+        let totals: [i64; STATE_COUNT] = std::array::from_fn(|state| state as i64);
+        let groups = group_totals(&totals);
+        assert_eq!(groups[0], 1 + 3);
+        assert_eq!(groups.iter().sum::<i64>(), totals.iter().sum::<i64>());
+    }
+
+    #[test]
+    fn group_counts_keep_moves_within_a_group_on_the_diagonal() {
+        let mut counts = [[0u64; STATE_COUNT]; STATE_COUNT];
+        // Study → Work stays in Productive, Work → Commute leaves it.
+        counts[0][1] = 3;
+        counts[1][2] = 2;
+        counts[2][0] = 1;
+        let grouped = group_counts(&counts);
+        assert_eq!(grouped[0][0], 3);
+        assert_eq!(grouped[0][2], 2);
+        assert_eq!(grouped[2][0], 1);
+        assert_eq!(grouped.iter().flatten().sum::<u64>(), 6);
+    }
+
+    #[test]
+    fn dst_days_are_not_24_hours() {
+        // London springs forward on 2026-03-29 and falls back on 2026-10-25.
+        let spring = calendar_window(&London, Period::Day, 0, at(&London, 2026, 3, 29, 12));
+        let spring = spring.unwrap();
+        assert_eq!(spring.to - spring.from, 23 * HOUR);
+
+        let autumn = calendar_window(&London, Period::Day, 0, at(&London, 2026, 10, 25, 12));
+        let autumn = autumn.unwrap();
+        assert_eq!(autumn.to - autumn.from, 25 * HOUR);
+    }
+
+    #[test]
+    fn day_windows_split_at_local_midnight_across_dst() {
+        let from = at(&London, 2026, 3, 28, 12);
+        let to = at(&London, 2026, 3, 30, 12);
+        let windows = period_windows(&London, from, to, Period::Day, 10).unwrap();
+        let labels: Vec<&str> = windows.iter().map(|w| w.label.as_str()).collect();
+        assert_eq!(labels, ["2026-03-28", "2026-03-29", "2026-03-30"]);
+
+        // One state held across the whole change still fills each day exactly.
+        let intervals = clip_intervals(&[(7, 0)], windows[0].from, windows[2].to, to + 99 * HOUR);
+        let totals = window_totals(&intervals, &windows);
+        assert_eq!(totals[0][7], 24 * HOUR);
+        assert_eq!(totals[1][7], 23 * HOUR);
+        assert_eq!(totals[2][7], 24 * HOUR);
+    }
+
+    #[test]
+    fn period_windows_respect_limit() {
+        let from = at(&UTC, 2026, 1, 1, 0);
+        let to = at(&UTC, 2026, 12, 31, 0);
+        assert_eq!(
+            period_windows(&UTC, from, to, Period::Month, 12)
+                .unwrap()
+                .len(),
+            12
+        );
+        assert!(period_windows(&UTC, from, to, Period::Day, 100).is_none());
+    }
+
+    #[test]
+    fn heatmap_places_time_by_local_weekday_and_hour() {
+        // Monday 2026-03-16 22:00 to Tuesday 01:30 UTC, so 23:00 to 02:30 in Paris.
+        let intervals = [Interval {
+            state: 7,
+            start: at(&UTC, 2026, 3, 16, 22),
+            end: at(&UTC, 2026, 3, 17, 1) + HOUR / 2,
+        }];
+        let grid = weekday_heatmap(&chrono_tz::Europe::Paris, &intervals, &[7], 60);
+        assert_eq!(grid.len(), 7);
+        assert_eq!(grid[0][23], HOUR);
+        assert_eq!(grid[1][0], HOUR);
+        assert_eq!(grid[1][1], HOUR);
+        assert_eq!(grid[1][2], HOUR / 2);
+        assert_eq!(grid.iter().flatten().sum::<i64>(), 3 * HOUR + HOUR / 2);
+
+        assert!(
+            weekday_heatmap(&UTC, &intervals, &[0], 30)
+                .iter()
+                .flatten()
+                .all(|&cell| cell == 0)
+        );
+    }
+
+    #[test]
+    fn transitions_count_only_filtered_changes() {
+        let entries = [
+            (7, at(&UTC, 2026, 3, 16, 0)),
+            (6, at(&UTC, 2026, 3, 16, 8)),
+            (0, at(&UTC, 2026, 3, 16, 9)),
+            (6, at(&UTC, 2026, 3, 16, 13)),
+            (0, at(&UTC, 2026, 3, 16, 14)),
+        ];
+        let now = at(&UTC, 2026, 3, 16, 18);
+
+        let all = transition_stats(&UTC, &entries, 0, now, &TimeFilter::default());
+        assert_eq!(all.counts[7][6], 1);
+        assert_eq!(all.counts[6][0], 2);
+        assert_eq!(all.counts[0][6], 1);
+        // The 14:00 session is still running, so only 09:00-13:00 counts.
+        assert_eq!(all.sessions[0], 1);
+        assert_eq!(all.dwell[0], 4 * HOUR);
+
+        // 08:00-11:59 is block 2 of six.
+        let morning = TimeFilter {
+            hour_bucket: 4,
+            hour: Some(2),
+            weekday: Some(0),
+        };
+        let morning = transition_stats(&UTC, &entries, 0, now, &morning);
+        assert_eq!(morning.counts[7][6], 1);
+        assert_eq!(morning.counts[6][0], 1);
+        assert_eq!(morning.counts[0][6], 0);
+    }
+
+    #[test]
+    fn distribution_of_session_lengths() {
+        let minute = 60_000;
+        let lengths: Vec<i64> = (1..=10).map(|m| m * 10 * minute).collect();
+        let distribution = Distribution::of(&lengths).unwrap();
+        assert_eq!(distribution.count, 10);
+        assert_eq!(distribution.mean, 55 * minute);
+        assert_eq!(distribution.median, 50 * minute);
+        assert_eq!(distribution.p10, 10 * minute);
+        assert_eq!(distribution.p90, 90 * minute);
+        assert_eq!(distribution.longest, 100 * minute);
+        assert_eq!(distribution.histogram.iter().sum::<u64>(), 10);
+        assert_eq!(distribution.histogram[2], 1); // 10m
+        assert_eq!(distribution.histogram[5], 5); // 60m to 100m
+
+        assert!(Distribution::of(&[]).is_none());
+    }
+
+    #[test]
+    fn sessions_keep_full_length_past_window() {
+        let entries = [(0, 0), (1, 10 * HOUR), (2, 20 * HOUR)];
+        let sessions = sessions_starting_in(&entries, 5 * HOUR, 15 * HOUR, 30 * HOUR);
+        assert_eq!(
+            sessions,
+            vec![Interval {
+                state: 1,
+                start: 10 * HOUR,
+                end: 20 * HOUR
+            }]
+        );
+
+        assert_eq!(
+            sessions_starting_in(&entries, 15 * HOUR, 30 * HOUR, 30 * HOUR).len(),
+            1
+        );
+        assert!(finished_sessions_starting_in(&entries, 15 * HOUR, 30 * HOUR).is_empty());
+    }
+
+    #[test]
+    fn focus_metrics_per_day() {
+        let minute = 60_000;
+        let day = at(&UTC, 2026, 3, 16, 0);
+        let entries = [
+            (7, day - 2 * HOUR),
+            (0, day + 8 * HOUR),
+            (6, day + 10 * HOUR),
+            (0, day + 10 * HOUR + 10 * minute),
+            (4, day + 11 * HOUR),
+        ];
+        let days = period_windows(&UTC, day, day + 1, Period::Day, 1).unwrap();
+        let focus = focus_by_day(&entries, &days, day + 12 * HOUR, &[0], 15 * minute);
+
+        assert_eq!(focus.len(), 1);
+        let focus = &focus[0];
+        assert_eq!(focus.switches, 4);
+        assert_eq!(focus.focus_blocks, 2);
+        assert_eq!(focus.focus_time, 2 * HOUR + 50 * minute);
+        assert_eq!(focus.mean_focus_block, Some(85 * minute));
+        assert_eq!(focus.short_block_time, 10 * minute);
+        assert_eq!(focus.tracked, 12 * HOUR);
+
+        // With 6 counting as focus too, 08:00-11:00 is a single block.
+        let focus = focus_by_day(&entries, &days, day + 12 * HOUR, &[0, 6], 15 * minute);
+        assert_eq!(focus[0].focus_blocks, 1);
+        assert_eq!(focus[0].focus_time, 3 * HOUR);
+        assert_eq!(focus[0].switches, 4);
+    }
+
+    #[test]
+    fn sleep_episodes_merge_short_wake_ups_and_split_naps() {
+        let minute = 60_000;
+        let night = at(&UTC, 2026, 3, 16, 23);
+        let entries = [
+            (7, night),
+            (6, night + 3 * HOUR),
+            (7, night + 3 * HOUR + 10 * minute),
+            (0, night + 8 * HOUR),
+            (7, night + 15 * HOUR),
+            (4, night + 16 * HOUR),
+        ];
+        let now = night + 20 * HOUR;
+        let episodes = sleep_episodes(&entries, 7, 0, now, now, 30 * minute);
+
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].start, night);
+        assert_eq!(episodes[0].end, night + 8 * HOUR);
+        assert_eq!(episodes[0].asleep, 7 * HOUR + 50 * minute);
+        assert_eq!(episodes[0].interruptions, 1);
+
+        let nights = group_nights(&UTC, &episodes);
+        assert_eq!(nights.len(), 1);
+        assert_eq!(
+            nights[0].date,
+            NaiveDate::from_ymd_opt(2026, 3, 17).unwrap()
+        );
+        assert_eq!(nights[0].main, episodes[0]);
+        assert_eq!(nights[0].naps, vec![episodes[1]]);
+    }
+
+    #[test]
+    fn regularity_index_rewards_a_fixed_schedule() {
+        let day = 24 * HOUR;
+        let regular: Vec<Interval> = (0..10)
+            .map(|d| Interval {
+                state: 7,
+                start: d * day,
+                end: d * day + 8 * HOUR,
+            })
+            .collect();
+        let sri = sleep_regularity_index(&regular, 0, 10 * day, HOUR / 4).unwrap();
+        assert!((sri - 100.0).abs() < 1e-9);
+
+        let shifting: Vec<Interval> = (0..10)
+            .map(|d| Interval {
+                state: 7,
+                start: d * day + (d % 2) * 12 * HOUR,
+                end: d * day + (d % 2) * 12 * HOUR + 8 * HOUR,
+            })
+            .collect();
+        assert!(sleep_regularity_index(&shifting, 0, 10 * day, HOUR / 4).unwrap() < 50.0);
+        assert!(sleep_regularity_index(&regular, 0, day / 2, HOUR).is_none());
+    }
+
+    #[test]
+    fn mean_time_of_day_wraps_midnight() {
+        assert_eq!(mean_time_of_day(&[23 * 60 + 30, 30]), Some(0));
+        assert_eq!(mean_time_of_day(&[22 * 60, 23 * 60]), Some(22 * 60 + 30));
+        assert_eq!(mean_time_of_day(&[]), None);
+    }
+
+    #[test]
+    fn skipped_midnight_starts_at_first_existing_hour() {
+        // São Paulo used to spring forward at midnight, so 2018-11-04 began at 01:00.
+        let date = NaiveDate::from_ymd_opt(2018, 11, 4).unwrap();
+        assert_eq!(
+            local_midnight(&Sao_Paulo, date),
+            Some(at(&Sao_Paulo, 2018, 11, 4, 1))
+        );
+    }
+}
@@ -13,22 +13,25 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use chrono_tz::Tz;
 use serde::Serialize;
-use sled::Tree;
 use std::{env, sync::LazyLock};
 
 pub static ACCESS_KEY: LazyLock<String> = LazyLock::new(|| env::var("ACCESS_KEY").unwrap());
 
-#[derive(Clone)]
-pub struct AppState {
-    pub events: Tree,
-    pub meta: Tree,
-}
+/// Timezone for local days, hours and calendar periods when a request doesn't
+/// name one. Set with `TIMEZONE`, e.g. `Europe/London`; UTC if unset.
+pub static DEFAULT_TIMEZONE: LazyLock<Tz> = LazyLock::new(|| match env::var("TIMEZONE") {
+    Ok(name) => name.trim().parse().expect("Unknown TIMEZONE."),
+    Err(_) => Tz::UTC,
+});
 
 pub const STATE_COUNT: usize = 15;
 
 pub const EMERGENCY_STATE_INDEX: usize = 14;
 
+pub const SLEEP_STATE_INDEX: usize = 7;
+
 #[derive(Clone, Copy, Serialize)]
 pub struct StateDetail<'a> {
     pub emoji: &'a str,
@@ -129,3 +132,40 @@ pub const ALL_STATES_DETAILS: [StateDetail; STATE_COUNT] = [
         colour: "#ff0000",
     },
 ];
+
+/// A named set of states that reports can add up, such as everything
+/// productive. Every state belongs to exactly one group.
+#[derive(Clone, Copy, Serialize)]
+pub struct StateGroup<'a> {
+    pub name: &'a str,
+    pub colour: &'a str,
+    pub states: &'a [usize],
+}
+
+pub const GROUP_COUNT: usize = 4;
+
+/// The group whose states count as focused work when no other set is asked for.
+pub const PRODUCTIVE_GROUP_INDEX: usize = 0;
+
+pub const ALL_STATE_GROUPS: [StateGroup; GROUP_COUNT] = [
+    StateGroup {
+        name: "Productive",
+        colour: "#4a71ea",
+        states: &[0, 1, 3],
+    },
+    StateGroup {
+        name: "Leisure",
+        colour: "#ffe066",
+        states: &[4, 5, 10, 11, 12],
+    },
+    StateGroup {
+        name: "Essential",
+        colour: "#b56a3b",
+        states: &[2, 6, 7, 8, 9],
+    },
+    StateGroup {
+        name: "Other",
+        colour: "#000000",
+        states: &[13, 14],
+    },
+];
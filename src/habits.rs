@@ -0,0 +1,345 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Daily habits checked against the log, such as "at least 30 minutes of Sports"
+//! or "no Entertainment before 18:00 on weekdays". Definitions are stored as
+//! JSON in the `habits` tree; this module only judges them.
+
+use chrono::{Datelike, NaiveTime, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analytics::{Interval, Window, local_date},
+    constants::STATE_COUNT,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Habit {
+    pub name: String,
+    /// The states the rule is about; time in any of them counts.
+    pub states: Vec<usize>,
+    pub rule: HabitRule,
+    /// Weekdays the habit applies on, 0 being Monday. Empty means every day.
+    #[serde(default)]
+    pub weekdays: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HabitRule {
+    /// At least this many minutes over the day.
+    AtLeast { minutes: u32 },
+    /// No more than this many minutes over the day.
+    AtMost { minutes: u32 },
+    /// None at all before this local time, as `HH:MM`.
+    NoneBefore { time: String },
+    /// None at all from this local time, as `HH:MM`, until midnight.
+    NoneAfter { time: String },
+}
+
+const MAX_HABIT_NAME_LENGTH: usize = 100;
+
+impl Habit {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.name.trim().is_empty() || self.name.len() > MAX_HABIT_NAME_LENGTH {
+            return Err("Bad request: Invalid habit name");
+        }
+        if self.states.is_empty() || self.states.iter().any(|&s| s >= STATE_COUNT) {
+            return Err("Bad request: Invalid state index");
+        }
+        if self.weekdays.iter().any(|&w| w >= 7) {
+            return Err("Bad request: Invalid weekday");
+        }
+        match &self.rule {
+            HabitRule::AtLeast { minutes } | HabitRule::AtMost { minutes } => {
+                if *minutes > 24 * 60 {
+                    return Err("Bad request: Invalid minutes");
+                }
+            }
+            HabitRule::NoneBefore { time } | HabitRule::NoneAfter { time } => {
+                if parse_time(time).is_none() {
+                    return Err("Bad request: Invalid time");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn applies_on(&self, weekday: u32) -> bool {
+        self.weekdays.is_empty() || self.weekdays.contains(&weekday)
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// Today, and it can still go either way.
+    Pending,
+    /// The habit doesn't apply on this weekday.
+    Skipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HabitDay {
+    pub date: String,
+    pub outcome: Outcome,
+    /// Time spent in the habit's states that day, in milliseconds.
+    pub total: i64,
+}
+
+/// Judges `habit` on each of `days` (ordered, non-overlapping local days, none
+/// starting after `now`) from `intervals`, which must be ordered and cover the
+/// days. Days that end before the first interval starts predate the log and are
+/// left out, rather than failing for lack of time.
+pub fn evaluate<Z: TimeZone>(
+    habit: &Habit,
+    tz: &Z,
+    intervals: &[Interval],
+    days: &[Window],
+    now: i64,
+) -> Vec<HabitDay> {
+    let Some(log_start) = intervals.first().map(|i| i.start) else {
+        return Vec::new();
+    };
+    days.iter()
+        .filter(|day| day.to > log_start)
+        .filter_map(|day| {
+            let date = local_date(tz, day.from)?;
+            let relevant: Vec<&Interval> = intervals
+                .iter()
+                .filter(|i| habit.states.contains(&i.state) && i.start < day.to && i.end > day.from)
+                .collect();
+            let time_in = |from: i64, to: i64| -> i64 {
+                relevant
+                    .iter()
+                    .map(|i| (i.end.min(to) - i.start.max(from)).max(0))
+                    .sum()
+            };
+            let total = time_in(day.from, day.to);
+
+            let outcome = if !habit.applies_on(date.weekday().num_days_from_monday()) {
+                Outcome::Skipped
+            } else {
+                let cutoff = |time: &str| {
+                    let time = parse_time(time)?;
+                    tz.from_local_datetime(&date.and_time(time))
+                        .earliest()
+                        .map(|t| t.timestamp_millis())
+                };
+                // Whether the verdict can still change: the day isn't over yet.
+                let open = now < day.to;
+                match &habit.rule {
+                    HabitRule::AtLeast { minutes } => match total >= i64::from(*minutes) * 60_000 {
+                        true => Outcome::Pass,
+                        false if open => Outcome::Pending,
+                        false => Outcome::Fail,
+                    },
+                    HabitRule::AtMost { minutes } => match total > i64::from(*minutes) * 60_000 {
+                        true => Outcome::Fail,
+                        false if open => Outcome::Pending,
+                        false => Outcome::Pass,
+                    },
+                    HabitRule::NoneBefore { time } => {
+                        // Skipped by DST: nothing can happen before it.
+                        let cutoff = cutoff(time).unwrap_or(day.from);
+                        match time_in(day.from, cutoff) > 0 {
+                            true => Outcome::Fail,
+                            false if now < cutoff => Outcome::Pending,
+                            false => Outcome::Pass,
+                        }
+                    }
+                    HabitRule::NoneAfter { time } => {
+                        let cutoff = cutoff(time).unwrap_or(day.to);
+                        match time_in(cutoff, day.to) > 0 {
+                            true => Outcome::Fail,
+                            false if open => Outcome::Pending,
+                            false => Outcome::Pass,
+                        }
+                    }
+                }
+            };
+
+            Some(HabitDay {
+                date: date.format("%Y-%m-%d").to_string(),
+                outcome,
+                total,
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Streaks {
+    /// Consecutive passes up to the latest decided day.
+    pub current: u32,
+    pub longest: u32,
+}
+
+/// Counts runs of passes. Skipped and pending days neither extend nor break a
+/// streak.
+pub fn streaks(days: &[HabitDay]) -> Streaks {
+    let mut streaks = Streaks::default();
+    for day in days {
+        match day.outcome {
+            Outcome::Pass => {
+                streaks.current += 1;
+                streaks.longest = streaks.longest.max(streaks.current);
+            }
+            Outcome::Fail => streaks.current = 0,
+            Outcome::Pending | Outcome::Skipped => {}
+        }
+    }
+    streaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::{Period, clip_intervals, period_windows};
+    use chrono_tz::UTC;
+
+    const HOUR: i64 = 3_600_000;
+
+    fn monday() -> i64 {
+        UTC.with_ymd_and_hms(2026, 3, 16, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn judge(habit: &Habit, entries: &[(usize, i64)], days: i64, now: i64) -> Vec<Outcome> {
+        let windows = period_windows(&UTC, monday(), monday() + days * 24 * HOUR, Period::Day, 31);
+        let windows = windows.unwrap();
+        let intervals = clip_intervals(entries, monday(), now, now);
+        evaluate(habit, &UTC, &intervals, &windows, now)
+            .into_iter()
+            .map(|day| day.outcome)
+            .collect()
+    }
+
+    #[test]
+    fn at_least_passes_fails_and_stays_pending_today() {
+        let habit = Habit {
+            name: "Sports".to_string(),
+            states: vec![11],
+            rule: HabitRule::AtLeast { minutes: 30 },
+            weekdays: Vec::new(),
+        };
+        let entries = [
+            (0, monday()),
+            (11, monday() + 10 * HOUR),
+            (0, monday() + 11 * HOUR),
+            (11, monday() + 48 * HOUR + 10 * HOUR),
+        ];
+        let now = monday() + 48 * HOUR + 10 * HOUR + HOUR / 4;
+        assert_eq!(
+            judge(&habit, &entries, 3, now),
+            [Outcome::Pass, Outcome::Fail, Outcome::Pending]
+        );
+    }
+
+    #[test]
+    fn days_before_the_log_are_left_out() {
+        let habit = Habit {
+            name: "Sports".to_string(),
+            states: vec![11],
+            rule: HabitRule::AtLeast { minutes: 30 },
+            weekdays: Vec::new(),
+        };
+        // The log starts on Wednesday afternoon; Monday and Tuesday have no data.
+        let entries = [
+            (11, monday() + 48 * HOUR + 14 * HOUR),
+            (0, monday() + 48 * HOUR + 15 * HOUR),
+        ];
+        let now = monday() + 4 * 24 * HOUR;
+        assert_eq!(
+            judge(&habit, &entries, 4, now),
+            [Outcome::Pass, Outcome::Fail]
+        );
+        assert!(judge(&habit, &[], 4, now).is_empty());
+    }
+
+    #[test]
+    fn none_before_respects_weekdays() {
+        let habit = Habit {
+            name: "No early TV".to_string(),
+            states: vec![4],
+            rule: HabitRule::NoneBefore {
+                time: "18:00".to_string(),
+            },
+            weekdays: vec![0, 1, 2, 3, 4],
+        };
+        // Entertainment from 12:00 every day, including Saturday and Sunday.
+        let entries: Vec<(usize, i64)> = (0..7)
+            .flat_map(|d| {
+                let day = monday() + d * 24 * HOUR;
+                [(0, day), (4, day + 12 * HOUR)]
+            })
+            .collect();
+        let outcomes = judge(&habit, &entries, 7, monday() + 7 * 24 * HOUR);
+        assert_eq!(&outcomes[..5], [Outcome::Fail; 5]);
+        assert_eq!(&outcomes[5..], [Outcome::Skipped; 2]);
+    }
+
+    #[test]
+    fn streaks_skip_unscheduled_days() {
+        let day = |outcome| HabitDay {
+            date: String::new(),
+            outcome,
+            total: 0,
+        };
+        let days = [
+            day(Outcome::Pass),
+            day(Outcome::Pass),
+            day(Outcome::Pass),
+            day(Outcome::Fail),
+            day(Outcome::Pass),
+            day(Outcome::Skipped),
+            day(Outcome::Pass),
+            day(Outcome::Pending),
+        ];
+        assert_eq!(
+            streaks(&days),
+            Streaks {
+                current: 2,
+                longest: 3
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_definitions() {
+        let mut habit = Habit {
+            name: "Sleep".to_string(),
+            states: vec![7],
+            rule: HabitRule::NoneAfter {
+                time: "25:00".to_string(),
+            },
+            weekdays: Vec::new(),
+        };
+        assert!(habit.validate().is_err());
+        habit.rule = HabitRule::NoneAfter {
+            time: "23:30".to_string(),
+        };
+        assert!(habit.validate().is_ok());
+        habit.states = vec![STATE_COUNT];
+        assert!(habit.validate().is_err());
+    }
+}
@@ -16,19 +16,51 @@
 use axum::{
     Json,
     extract::{Path, Query, State},
-    http::{Method, StatusCode, Uri},
+    http::{Method, StatusCode, Uri, header},
     response::{IntoResponse, Response},
 };
-use chrono::{FixedOffset, Utc};
+use chrono::{Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use sled::{IVec, Transactional, transaction::TransactionResult};
+use std::collections::BTreeMap;
 
 use crate::{
-    constants::{ALL_STATES_DETAILS, AppState, EMERGENCY_STATE_INDEX, STATE_COUNT, StateDetail},
-    predictor::{ActivityPredictor, Configuration, TrainingEntry},
+    AppState,
+    accuracy::{
+        Accuracy, Challenger, Comparison, PendingSuggestion, ScoredTransition, ShadowSuggestion,
+        accuracy, accuracy_by_state, compare,
+    },
+    analytics::{
+        Baseline, DayFocus, Deviation, Distribution, GroupBy, HISTOGRAM_BINS, Interval, Period,
+        SplitBy, TimeFilter, Window, baseline_windows, calendar_window, clip_intervals, deviations,
+        explicit_window, finished_sessions_starting_in, focus_by_day, group_counts, group_nights,
+        group_totals, local_date, mean_time_of_day, minutes_into_day, period_windows,
+        sessions_starting_in, sleep_episodes, sleep_regularity_index, state_totals, tally_year,
+        transition_stats, weekday_heatmap, weekday_norms, window_totals,
+    },
+    constants::{
+        ALL_STATE_GROUPS, ALL_STATES_DETAILS, DEFAULT_TIMEZONE, EMERGENCY_STATE_INDEX, GROUP_COUNT,
+        PRODUCTIVE_GROUP_INDEX, SLEEP_STATE_INDEX, STATE_COUNT, StateDetail, StateGroup,
+    },
+    habits::{Habit, HabitDay, Outcome, Streaks, evaluate as evaluate_habit, streaks},
+    predictor::{
+        ActivityPredictor, Configuration, DurationContext, Explanation, MAX_TRAINING_ENTRIES,
+        Preset, TrainingEntry,
+    },
+    predictor_cache::with_uncached_predictor,
+    report::{
+        AnomalyLine, GroupLine, HabitLine, Report, ReportFormat, SessionLine, SleepSummary,
+        StateLine, render_html, render_markdown,
+    },
+    timezone::Zone,
+    tuning::{CandidateResult, SearchSpace, TuningJob, eval_window, tune},
     utils::{
-        get_length, incr_length, is_reasonable_timestamp, is_valid_timestamp, ivec_to_u64,
-        log_corrupt_entry, read_from_value, to_ivec, try_read_from_value,
+        bump_history_version, get_history_version, get_length, get_predictor_challenger,
+        get_predictor_configuration, incr_length, is_reasonable_timestamp, is_valid_timestamp,
+        ivec_to_u64, log_corrupt_entry, parse_states, parse_timezone, read_from_value,
+        read_valid_entries, set_predictor_challenger, set_predictor_configuration, to_ivec,
+        try_read_from_value,
     },
 };
 
@@ -46,6 +78,7 @@ pub struct StatesResponse<'a> {
     state_count: usize,
     emergency_state_index: usize,
     states: [StateDetail<'a>; STATE_COUNT],
+    groups: [StateGroup<'a>; GROUP_COUNT],
 }
 
 pub async fn fetch_states() -> Response {
@@ -56,6 +89,7 @@ pub async fn fetch_states() -> Response {
             state_count: STATE_COUNT,
             emergency_state_index: EMERGENCY_STATE_INDEX,
             states: ALL_STATES_DETAILS,
+            groups: ALL_STATE_GROUPS,
         }),
     )
         .into_response()
@@ -144,6 +178,7 @@ pub async fn add_entry(
     }
 
     incr_length(&state.meta);
+    score_pending_suggestion(&state, new_key, new_state as usize, start_timestamp);
 
     let response = AddEntryResponse {
         entry_idx: new_key,
@@ -269,12 +304,17 @@ pub async fn update_entry(
     bytes[0] = new_state;
     bytes[1..].copy_from_slice(&start_timestamp.to_ne_bytes());
 
-    match state.events.insert(to_ivec(entry_idx), IVec::from(&bytes)) {
-        Ok(_) => (),
-        Err(err) => {
-            println!("{err:?}");
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
-        }
+    // The bump commits with the edit, so a predictor can't see one without the
+    // other.
+    let result: TransactionResult<(), sled::Error> =
+        (&state.events, &state.meta).transaction(|(tx_events, tx_meta)| {
+            tx_events.insert(to_ivec(entry_idx), IVec::from(&bytes))?;
+            bump_history_version(tx_meta)?;
+            Ok(())
+        });
+    if let Err(err) = result {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
     }
 
     let response = UpdateEntryResponse {
@@ -323,271 +363,2782 @@ pub async fn get_entry(Path(entry_idx): Path<u64>, State(state): State<AppState>
 
 #[derive(Deserialize)]
 pub struct FetchSummaryDataRequest {
+    /// Length of a trailing window ending now. Defaults to 7, and is ignored when
+    /// `from` or `period` is given.
     days: Option<u32>,
+    /// Start of an explicit window, in epoch milliseconds.
+    from: Option<i64>,
+    /// End of an explicit window, in epoch milliseconds. Defaults to now.
+    to: Option<i64>,
+    /// Calendar unit to align the window to, instead of `from`/`to`.
+    period: Option<Period>,
+    /// How many periods to shift by: 0 is the current one, -1 the one before.
+    offset: Option<i32>,
+    /// IANA timezone that calendar periods are aligned in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// `group` also adds the totals up per state group. Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Serialize)]
+pub struct SummaryResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    totals: [i64; STATE_COUNT],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_totals: Option<[i64; GROUP_COUNT]>,
+    tracked: i64,
+    untracked: i64,
+}
+
+const MAX_PERIOD_OFFSET: i32 = 10_000;
+
+/// Resolves the `from`/`to` or `period`/`offset` half of a query into a window,
+/// falling back to the trailing `default_days` when neither is given. Shared by
+/// every endpoint that reports over an explicit or calendar-aligned window.
+fn resolve_window(
+    tz: &Tz,
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    now: i64,
+    default_days: i64,
+) -> Result<Window, &'static str> {
+    match (period, from, to) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            Err("Bad request: period cannot be combined with from/to")
+        }
+        (Some(period), None, None) => {
+            let offset = offset.unwrap_or(0);
+            if offset.abs() > MAX_PERIOD_OFFSET {
+                return Err("Bad request: Invalid offset");
+            }
+            calendar_window(tz, period, offset, now).ok_or("Bad request: Invalid offset")
+        }
+        (None, Some(from), to) => {
+            let to = to.unwrap_or(now);
+            if !is_valid_timestamp(from) || !is_valid_timestamp(to) {
+                return Err("Bad request: Invalid timestamp");
+            }
+            if from >= to {
+                return Err("Bad request: from must be earlier than to");
+            }
+            explicit_window(tz, from, to).ok_or("Bad request: Invalid timestamp")
+        }
+        (None, None, Some(_)) => Err("Bad request: to requires from"),
+        (None, None, None) => explicit_window(tz, now - default_days * 24 * 3600 * 1000, now)
+            .ok_or("Bad request: Invalid timestamp"),
+    }
 }
 
 pub async fn fetch_summary_data(
     Query(params): Query<FetchSummaryDataRequest>,
     State(state): State<AppState>,
 ) -> Response {
-    // Very naive brute-force approach just to get the thing working
-    let len = get_length(&state.meta);
-
-    let mut cumulative = [0i64; STATE_COUNT];
+    let FetchSummaryDataRequest {
+        days,
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        group_by,
+    } = params;
 
-    if len == 0 {
+    let now = Utc::now().timestamp_millis();
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_summary_data");
+    let by_group = group_by == Some(GroupBy::Group);
+
+    // Without an explicit window, keep answering with the bare per-state array
+    // over the trailing `days`, which is what existing clients expect. Group
+    // totals don't fit in that array, so asking for them gets the full response.
+    let trailing_days = i64::from(days.unwrap_or(7u32));
+    if from.is_none() && to.is_none() && period.is_none() && !by_group {
+        let range_start = now - trailing_days * 24 * 3600 * 1000;
+        let cumulative = state_totals(&clip_intervals(&entries, range_start, now, now));
         return (StatusCode::OK, Json(cumulative)).into_response();
     }
 
-    let mut old_state: Option<u8> = None;
-    let mut old_timestamp: Option<i64> = None;
-
-    let curr_time = Utc::now().timestamp_millis();
-    let range_start = curr_time - params.days.unwrap_or(7u32) as i64 * 24 * 3600 * 1000;
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
 
-    let mut pre_range_start_state: Option<u8> = None;
+    let window = match resolve_window(&tz, from, to, period, offset, now, trailing_days) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
 
-    for i in 0..len {
-        let (state, timestamp) = read_from_value(&state.events, i);
-        if !is_valid_timestamp(timestamp) {
-            log_corrupt_entry("fetch_summary_data", i, state, timestamp);
-        }
-        if timestamp < range_start {
-            pre_range_start_state = Some(state);
-            continue;
-        }
+    let totals = state_totals(&clip_intervals(&entries, window.from, window.to, now));
+    let tracked = totals.iter().sum::<i64>();
+    // Only the part of the window that has already happened can be untracked.
+    let elapsed = (window.to.min(now) - window.from).max(0);
 
-        if let Some(val) = old_state {
-            cumulative[val as usize] += timestamp - old_timestamp.unwrap();
-        } else if let Some(pre_start_state) = pre_range_start_state {
-            cumulative[pre_start_state as usize] += timestamp - range_start;
-        }
-        old_timestamp = Some(timestamp);
-        old_state = Some(state);
-    }
+    (
+        StatusCode::OK,
+        Json(SummaryResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            totals,
+            group_totals: by_group.then(|| group_totals(&totals)),
+            tracked,
+            untracked: (elapsed - tracked).max(0),
+        }),
+    )
+        .into_response()
+}
 
-    if let Some(val) = old_state {
-        cumulative[val as usize] += curr_time - old_timestamp.unwrap();
-    } else if let Some(pre_start_state) = pre_range_start_state {
-        cumulative[pre_start_state as usize] += curr_time - range_start;
-    }
+#[derive(Deserialize)]
+pub struct FetchComparisonRequest {
+    /// Calendar unit to compare. Defaults to `week`.
+    period: Option<Period>,
+    /// Which period to report on, 0 being the current one. Defaults to 0.
+    offset: Option<i32>,
+    /// What to compare against. Defaults to `previous`.
+    against: Option<Baseline>,
+    /// Only count the part of each baseline period that has elapsed in the
+    /// current one, so a Wednesday is compared with last Monday–Wednesday.
+    /// Defaults to false.
+    to_date: Option<bool>,
+    /// IANA timezone that periods are aligned to. Defaults to the server's
+    /// `TIMEZONE`.
+    tz: Option<String>,
+    /// `group` also compares per state group. Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
 
-    (StatusCode::OK, Json(cumulative)).into_response()
+#[derive(Serialize)]
+pub struct StateComparison {
+    current: i64,
+    baseline: i64,
+    delta: i64,
+    /// `delta` relative to `baseline`, in percent. `None` when the baseline is zero.
+    percent: Option<f64>,
 }
 
-pub async fn fetch_length(State(state): State<AppState>) -> Response {
-    let length = get_length(&state.meta);
+#[derive(Serialize)]
+pub struct ComparisonResponse {
+    period: Period,
+    against: Baseline,
+    current: Window,
+    /// The windows the baseline is averaged over, oldest first.
+    baseline: Vec<Window>,
+    states: Vec<StateComparison>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<StateComparison>>,
+    tracked: StateComparison,
+}
 
-    (StatusCode::OK, Json(length)).into_response()
+/// Mean per-state totals over `windows`, which must not be empty.
+fn average_totals(entries: &[(usize, i64)], windows: &[Window], now: i64) -> [i64; STATE_COUNT] {
+    // Windows never overlap, but may leave gaps between them once cut down to
+    // date, so each is clipped on its own.
+    let mut sums = [0i64; STATE_COUNT];
+    for window in windows {
+        let totals = state_totals(&clip_intervals(entries, window.from, window.to, now));
+        for (sum, total) in sums.iter_mut().zip(totals) {
+            *sum += total;
+        }
+    }
+    let count = windows.len().max(1) as i64;
+    sums.map(|sum| sum / count)
 }
 
-#[derive(Deserialize)]
-pub struct ForceSetLengthRequest {
-    new_length: u64,
+fn compare_totals(current: i64, baseline: i64) -> StateComparison {
+    StateComparison {
+        current,
+        baseline,
+        delta: current - baseline,
+        percent: (baseline != 0).then(|| (current - baseline) as f64 / baseline as f64 * 100.0),
+    }
 }
 
-pub async fn force_set_length(
+/// Per-state totals of a calendar period next to a baseline: the previous
+/// period, the average of the four before it, or the same period last year.
+pub async fn fetch_comparison(
+    Query(params): Query<FetchComparisonRequest>,
     State(state): State<AppState>,
-    Json(payload): Json<ForceSetLengthRequest>,
 ) -> Response {
-    let ForceSetLengthRequest { new_length } = payload;
+    let FetchComparisonRequest {
+        period,
+        offset,
+        against,
+        to_date,
+        tz,
+        group_by,
+    } = params;
+    let period = period.unwrap_or(Period::Week);
+    let against = against.unwrap_or(Baseline::Previous);
 
-    let v = to_ivec(new_length);
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
 
-    // TO-DO: Handle Err(_) gracefully
-    state.meta.insert(b"len", v).unwrap();
+    let now = Utc::now().timestamp_millis();
+    let current = match resolve_window(&tz, None, None, Some(period), offset, now, 0) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let Some(mut baseline) = local_date(&tz, current.from)
+        .and_then(|start| baseline_windows(&tz, start, period, against))
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid offset").into_response();
+    };
 
-    (StatusCode::OK, Json(new_length)).into_response()
+    if to_date.unwrap_or(false) {
+        let elapsed = (current.to.min(now) - current.from).max(0);
+        for window in &mut baseline {
+            window.to = window.to.min(window.from + elapsed);
+        }
+    }
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_comparison");
+    let current_totals = state_totals(&clip_intervals(&entries, current.from, current.to, now));
+    let baseline_totals = average_totals(&entries, &baseline, now);
+
+    let states = current_totals
+        .iter()
+        .zip(&baseline_totals)
+        .map(|(&current, &baseline)| compare_totals(current, baseline))
+        .collect();
+    let groups = (group_by == Some(GroupBy::Group)).then(|| {
+        group_totals(&current_totals)
+            .iter()
+            .zip(&group_totals(&baseline_totals))
+            .map(|(&current, &baseline)| compare_totals(current, baseline))
+            .collect()
+    });
+    let tracked = compare_totals(current_totals.iter().sum(), baseline_totals.iter().sum());
+
+    (
+        StatusCode::OK,
+        Json(ComparisonResponse {
+            period,
+            against,
+            current,
+            baseline,
+            states,
+            groups,
+            tracked,
+        }),
+    )
+        .into_response()
 }
 
 #[derive(Deserialize)]
-pub struct SuggestRequest {
-    /// How many activities to suggest. Defaults to 3.
-    limit: Option<usize>,
-    /// The client's UTC offset in minutes, east of Greenwich. Weekday and
-    /// hour-of-day context is derived in this timezone, so a client in a
-    /// non-UTC zone must pass it or the time-of-day patterns will be skewed.
-    tz_offset: Option<i32>,
-    /// Moment to predict for, in epoch milliseconds. Defaults to now.
-    at: Option<i64>,
-    /// State to predict away from. Defaults to the latest recorded state, and
-    /// is never itself suggested.
-    current_state: Option<u8>,
-    /// How many of the most recent entries to train on. Defaults to 10000.
-    max_entries: Option<usize>,
+pub struct FetchAnomaliesRequest {
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that days are taken in. Defaults to the server's
+    /// `TIMEZONE`.
+    tz: Option<String>,
+    /// How many weeks before the window the norms are learnt from. Defaults to 12.
+    history_weeks: Option<u32>,
+    /// How many robust standard deviations make a day unusual. Defaults to 3.
+    threshold: Option<f64>,
+    /// `group` also judges each day's totals per state group against their own
+    /// norms. Defaults to `state`.
+    group_by: Option<GroupBy>,
 }
 
+/// A [`Deviation`] of one state group's total.
 #[derive(Serialize)]
-pub struct Suggestion {
-    state: u8,
-    name: &'static str,
-    emoji: &'static str,
-    colour: &'static str,
+pub struct GroupDeviation {
+    group: usize,
+    total: i64,
+    median: i64,
+    score: f64,
 }
 
 #[derive(Serialize)]
-pub struct SuggestResponse {
-    at: i64,
-    current_state: Option<u8>,
-    trained_on: usize,
-    suggestions: Vec<Suggestion>,
+pub struct AnomalousDay {
+    date: String,
+    /// 0 is Monday.
+    weekday: u32,
+    deviations: Vec<Deviation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_deviations: Option<Vec<GroupDeviation>>,
 }
 
-const MAX_TZ_OFFSET_MINUTES: i32 = 14 * 60;
-const MAX_TRAINING_ENTRIES: usize = 100_000;
+#[derive(Serialize)]
+pub struct AnomaliesResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    /// Norms were learnt from `[history_from, from)`.
+    history_from: i64,
+    /// Days that were checked; the rest had no norm for their weekday yet.
+    checked: usize,
+    days: Vec<AnomalousDay>,
+}
 
-/// Suggests the activities most likely to come next, using the TAGE predictor in
-/// [`crate::predictor`]. Training happens per request over the tail of the log,
-/// which keeps the clients stateless — they no longer need to download the whole
-/// history to predict locally.
-pub async fn suggest_next_states(
-    Query(params): Query<SuggestRequest>,
+const DEFAULT_HISTORY_WEEKS: u32 = 12;
+const MAX_HISTORY_WEEKS: u32 = 104;
+const DEFAULT_ANOMALY_THRESHOLD: f64 = 3.0;
+const MAX_ANOMALY_DAYS: usize = 2_000;
+/// A weekday needs this many past days before it has a norm.
+const MIN_NORM_SAMPLES: usize = 4;
+/// Days with less tracked time than this (before the first entry, say) are
+/// neither learnt from nor judged.
+const MIN_TRACKED_FRACTION: f64 = 0.5;
+/// Floor on the spread of a state, so states that barely vary aren't flagged
+/// over a few minutes.
+const MIN_DEVIATION_SCALE_MS: i64 = 15 * 60 * 1000;
+
+/// Judges the complete days of `window` against weekday norms learnt from the
+/// `history_weeks` before it. Returns where the history starts, how many days
+/// had a norm to be judged against, and the unusual ones. With `by_group`, group
+/// totals are judged as well, and a day that is only unusual per group counts.
+fn find_anomalies(
+    tz: &Tz,
+    entries: &[(usize, i64)],
+    window: &Window,
+    history_weeks: u32,
+    threshold: f64,
+    by_group: bool,
+    now: i64,
+) -> Result<(i64, usize, Vec<AnomalousDay>), &'static str> {
+    let history_from = window.from - i64::from(history_weeks) * 7 * 24 * 3600 * 1000;
+
+    // Only whole days are judged: today's Work might still be coming.
+    let Some(mut days) = period_windows(tz, history_from, window.to, Period::Day, MAX_ANOMALY_DAYS)
+    else {
+        return Err("Bad request: Too many days");
+    };
+    days.retain(|day| day.from >= history_from && day.to <= now);
+
+    let intervals = match (days.first(), days.last()) {
+        (Some(first), Some(last)) => clip_intervals(entries, first.from, last.to, now),
+        _ => Vec::new(),
+    };
+    let totals = window_totals(&intervals, &days);
+
+    let mut history = Vec::new();
+    let mut judged = Vec::new();
+    for (day, totals) in days.iter().zip(totals) {
+        let tracked = totals.iter().sum::<i64>();
+        if (tracked as f64) < (day.to - day.from) as f64 * MIN_TRACKED_FRACTION {
+            continue;
+        }
+        let Some(date) = local_date(tz, day.from) else {
+            continue;
+        };
+        let weekday = date.weekday().num_days_from_monday();
+        if day.from < window.from {
+            history.push((weekday, totals));
+        } else if day.to <= window.to {
+            judged.push((day.label.clone(), weekday, totals));
+        }
+    }
+
+    let norms = weekday_norms(&history, MIN_NORM_SAMPLES);
+    // Group totals come from the same days, so a weekday with a norm per state
+    // has one per group too.
+    let group_norms = by_group.then(|| {
+        let history: Vec<(u32, [i64; GROUP_COUNT])> = history
+            .iter()
+            .map(|(weekday, totals)| (*weekday, group_totals(totals)))
+            .collect();
+        weekday_norms(&history, MIN_NORM_SAMPLES)
+    });
+    let mut checked = 0;
+    let days = judged
+        .into_iter()
+        .filter_map(|(date, weekday, totals)| {
+            let norm = norms[weekday as usize].as_ref()?;
+            checked += 1;
+            let group_deviations = group_norms.as_ref().and_then(|norms| {
+                let norm = norms[weekday as usize].as_ref()?;
+                let found = deviations(
+                    &group_totals(&totals),
+                    norm,
+                    MIN_DEVIATION_SCALE_MS,
+                    threshold,
+                );
+                Some(
+                    found
+                        .into_iter()
+                        .map(|deviation| GroupDeviation {
+                            group: deviation.state,
+                            total: deviation.total,
+                            median: deviation.median,
+                            score: deviation.score,
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            });
+            let deviations = deviations(&totals, norm, MIN_DEVIATION_SCALE_MS, threshold);
+            let unusual = !deviations.is_empty()
+                || group_deviations
+                    .as_ref()
+                    .is_some_and(|found| !found.is_empty());
+            unusual.then_some(AnomalousDay {
+                date,
+                weekday,
+                deviations,
+                group_deviations,
+            })
+        })
+        .collect();
+
+    Ok((history_from, checked, days))
+}
+
+/// Complete days in the window whose state mix is far from the norm for their
+/// weekday, as learnt from the weeks before. Defaults to the last 30 days.
+pub async fn fetch_anomalies(
+    Query(params): Query<FetchAnomaliesRequest>,
     State(state): State<AppState>,
 ) -> Response {
-    let SuggestRequest {
-        limit,
-        tz_offset,
-        at,
-        current_state,
-        max_entries,
+    let FetchAnomaliesRequest {
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        history_weeks,
+        threshold,
+        group_by,
     } = params;
+    let by_group = group_by == Some(GroupBy::Group);
 
-    let limit = limit.unwrap_or(3);
-    if limit == 0 || limit > STATE_COUNT {
-        return (StatusCode::BAD_REQUEST, "Bad request: Invalid limit").into_response();
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+    let history_weeks = history_weeks.unwrap_or(DEFAULT_HISTORY_WEEKS);
+    if history_weeks == 0 || history_weeks > MAX_HISTORY_WEEKS {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Bad request: Invalid history_weeks",
+        )
+            .into_response();
+    }
+    let threshold = threshold.unwrap_or(DEFAULT_ANOMALY_THRESHOLD);
+    if !threshold.is_finite() || threshold <= 0.0 {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid threshold").into_response();
     }
 
-    let tz_offset = tz_offset.unwrap_or(0);
-    let Some(offset) = (tz_offset.abs() <= MAX_TZ_OFFSET_MINUTES)
-        .then(|| FixedOffset::east_opt(tz_offset * 60))
-        .flatten()
-    else {
-        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz_offset").into_response();
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 30) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
     };
 
-    let at = at.unwrap_or_else(|| Utc::now().timestamp_millis());
-    if !is_valid_timestamp(at) {
-        return (StatusCode::BAD_REQUEST, "Bad request: Invalid timestamp").into_response();
-    }
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_anomalies");
+    let (history_from, checked, days) = match find_anomalies(
+        &tz,
+        &entries,
+        &window,
+        history_weeks,
+        threshold,
+        by_group,
+        now,
+    ) {
+        Ok(found) => found,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
 
-    if current_state.is_some_and(|s| s as usize >= STATE_COUNT) {
-        return (StatusCode::BAD_REQUEST, "Bad request: Invalid state index").into_response();
-    }
+    (
+        StatusCode::OK,
+        Json(AnomaliesResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            history_from,
+            checked,
+            days,
+        }),
+    )
+        .into_response()
+}
 
-    let max_entries = max_entries.unwrap_or(10_000);
-    if max_entries == 0 || max_entries > MAX_TRAINING_ENTRIES {
-        return (StatusCode::BAD_REQUEST, "Bad request: Invalid max_entries").into_response();
-    }
+#[derive(Deserialize)]
+pub struct FetchSeriesRequest {
+    /// Start of the series, in epoch milliseconds. Defaults to 30 days before `to`.
+    from: Option<i64>,
+    /// End of the series, in epoch milliseconds. Defaults to now.
+    to: Option<i64>,
+    /// Bucket width. Defaults to `day`.
+    bucket: Option<Period>,
+    /// IANA timezone that bucket boundaries fall on. Defaults to the server's
+    /// `TIMEZONE`.
+    tz: Option<String>,
+    /// `group` also adds each bucket up per state group. Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
 
-    // Reading the log and training over it is CPU-bound and can span tens of
-    // thousands of entries, so keep it off the async runtime's worker threads.
-    let predicted = tokio::task::spawn_blocking(move || {
-        let length = get_length(&state.meta);
-        let start = length.saturating_sub(max_entries as u64);
-
-        let mut entries = Vec::with_capacity((length - start) as usize);
-        for i in start..length {
-            let (state_id, timestamp) = read_from_value(&state.events, i);
-            // A state index out of range or an unrepresentable timestamp can't be
-            // placed in the training sequence at all, so log and drop it rather
-            // than let one corrupt row skew every prediction.
-            if state_id as usize >= STATE_COUNT || !is_valid_timestamp(timestamp) {
-                log_corrupt_entry("suggest_next_states", i, state_id, timestamp);
-                continue;
-            }
-            entries.push(TrainingEntry {
-                state_id: state_id as usize,
-                start_timestamp: timestamp,
-            });
-        }
+#[derive(Serialize)]
+pub struct SeriesBucket {
+    from: i64,
+    to: i64,
+    label: String,
+    totals: [i64; STATE_COUNT],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_totals: Option<[i64; GROUP_COUNT]>,
+    untracked: i64,
+}
 
-        let current_state = current_state.or_else(|| entries.last().map(|e| e.state_id as u8));
+#[derive(Serialize)]
+pub struct SeriesResponse {
+    bucket: Period,
+    tz: String,
+    buckets: Vec<SeriesBucket>,
+}
 
-        let configuration = Configuration {
-            maximum_training_entries: max_entries,
-            ..Configuration::default()
-        };
-        let trained_on = entries.len();
-        let predictor = ActivityPredictor::new(&entries, offset, configuration);
-        let states = predictor.predictions(at, current_state.map(|s| s as usize), limit);
+const MAX_SERIES_BUCKETS: usize = 1_000;
 
-        (current_state, trained_on, states)
-    })
-    .await;
+/// Per-state totals for each day, week or month between `from` and `to`, widened
+/// to whole buckets. Boundaries are local midnights in `tz`, so a DST day is 23
+/// or 25 hours long and still adds up to the whole day.
+pub async fn fetch_series(
+    Query(params): Query<FetchSeriesRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchSeriesRequest {
+        from,
+        to,
+        bucket,
+        tz,
+        group_by,
+    } = params;
+    let by_group = group_by == Some(GroupBy::Group);
 
-    let (current_state, trained_on, states) = match predicted {
-        Ok(result) => result,
-        Err(err) => {
-            println!("{err:?}");
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
-        }
+    let now = Utc::now().timestamp_millis();
+    let to = to.unwrap_or(now);
+    if !is_valid_timestamp(to) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid timestamp").into_response();
+    }
+    let from = from.unwrap_or(to - 30 * 24 * 3600 * 1000);
+    if !is_valid_timestamp(from) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid timestamp").into_response();
+    }
+    if from >= to {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Bad request: from must be earlier than to",
+        )
+            .into_response();
+    }
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+    let bucket = bucket.unwrap_or(Period::Day);
+
+    let Some(windows) = period_windows(&tz, from, to, bucket, MAX_SERIES_BUCKETS) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Too many buckets").into_response();
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_series");
+    let (series_from, series_to) = match (windows.first(), windows.last()) {
+        (Some(first), Some(last)) => (first.from, last.to),
+        _ => (from, from),
     };
+    let intervals = clip_intervals(&entries, series_from, series_to, now);
+    let totals = window_totals(&intervals, &windows);
 
-    let suggestions = states
+    let buckets = windows
         .into_iter()
-        .map(|state_id| {
-            let detail = ALL_STATES_DETAILS[state_id];
-            Suggestion {
-                state: state_id as u8,
-                name: detail.name,
-                emoji: detail.emoji,
-                colour: detail.colour,
+        .zip(totals)
+        .map(|(window, totals)| {
+            let elapsed = (window.to.min(now) - window.from).max(0);
+            SeriesBucket {
+                untracked: (elapsed - totals.iter().sum::<i64>()).max(0),
+                from: window.from,
+                to: window.to,
+                label: window.label,
+                group_totals: by_group.then(|| group_totals(&totals)),
+                totals,
             }
         })
         .collect();
 
     (
         StatusCode::OK,
-        Json(SuggestResponse {
-            at,
-            current_state,
-            trained_on,
-            suggestions,
+        Json(SeriesResponse {
+            bucket,
+            tz: tz.name().to_string(),
+            buckets,
         }),
     )
         .into_response()
 }
 
 #[derive(Deserialize)]
-pub struct FetchRecentsRequest {
-    count: Option<u64>,
-    days: Option<u32>,
+pub struct FetchHeatmapRequest {
+    /// Comma-separated state indices to count. Defaults to every state.
+    state: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that weekdays and hours are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// Width of a column, in minutes. Must divide a day evenly. Defaults to 60.
+    bucket_minutes: Option<u32>,
+    /// `group` also returns a grid per state group, whatever `state` is.
+    /// Defaults to `state`.
+    group_by: Option<GroupBy>,
 }
 
-pub async fn fetch_recent_states(
-    Query(params): Query<FetchRecentsRequest>,
+#[derive(Serialize)]
+pub struct HeatmapResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    states: Vec<usize>,
+    bucket_minutes: u32,
+    /// Seven rows, Monday first, of minutes per column.
+    minutes: Vec<Vec<f64>>,
+    /// One grid like `minutes` per state group, in group order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_minutes: Option<Vec<Vec<Vec<f64>>>>,
+}
+
+/// When a state (or any of several) happens: minutes per weekday and
+/// time-of-day slot over a window, defaulting to the last four weeks.
+pub async fn fetch_heatmap(
+    Query(params): Query<FetchHeatmapRequest>,
     State(state): State<AppState>,
 ) -> Response {
-    let length = get_length(&state.meta);
+    let FetchHeatmapRequest {
+        state: states,
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        bucket_minutes,
+        group_by,
+    } = params;
 
-    // If the user doesn't pass in either param, we use these very large defaults.
-    let count = length.min(params.count.unwrap_or(300u64));
-    let days = params.days.unwrap_or(30u32) as i64;
+    let states = match states {
+        Some(list) => match parse_states(&list) {
+            Some(states) => states,
+            None => {
+                return (StatusCode::BAD_REQUEST, "Bad request: Invalid state index")
+                    .into_response();
+            }
+        },
+        None => (0..STATE_COUNT).collect(),
+    };
 
-    // User is guarded against specifying params.count = 0 by frontend, but we
-    //   should return an empty vector rather than panic with out-of-bounds access.
-    // This also happens if length == 0.
-    if count == 0 {
-        return (StatusCode::OK, Json(Vec::<(u8, i64)>::new())).into_response();
+    let bucket_minutes = bucket_minutes.unwrap_or(60);
+    if bucket_minutes < 5 || 1440 % bucket_minutes != 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Bad request: Invalid bucket_minutes",
+        )
+            .into_response();
     }
 
-    let curr_time = Utc::now().timestamp_millis();
-    let range_start = curr_time - days * 24 * 3600 * 1000;
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
 
-    let mut output = Vec::<(u8, i64)>::new();
-    for i in ((length - count)..=(length - 1)).rev() {
-        let (s, t) = read_from_value(&state.events, i);
-        if !is_valid_timestamp(t) {
-            log_corrupt_entry("fetch_recent_states", i, s, t);
-        }
-        output.push((s, t));
-        if t < range_start {
-            break;
-        }
-    }
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 28) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
 
-    (StatusCode::OK, Json(output)).into_response()
-}
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_heatmap");
+    let intervals = clip_intervals(&entries, window.from, window.to, now);
+    let heatmap_minutes = |states: &[usize]| -> Vec<Vec<f64>> {
+        weekday_heatmap(&tz, &intervals, states, bucket_minutes)
+            .into_iter()
+            .map(|row| row.into_iter().map(|ms| ms as f64 / 60_000.0).collect())
+            .collect()
+    };
+    let minutes = heatmap_minutes(&states);
+    let group_minutes = (group_by == Some(GroupBy::Group)).then(|| {
+        ALL_STATE_GROUPS
+            .iter()
+            .map(|group| heatmap_minutes(group.states))
+            .collect()
+    });
+
+    (
+        StatusCode::OK,
+        Json(HeatmapResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            states,
+            bucket_minutes,
+            minutes,
+            group_minutes,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchTransitionsRequest {
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that `hour` and `weekday` are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// Width of an hour block, as in the predictor's `hour_bucket`. Defaults to 4.
+    hour_bucket: Option<u32>,
+    /// Only count transitions in this hour block, 0 being the one from midnight.
+    hour: Option<u32>,
+    /// Only count transitions on this weekday, 0 being Monday.
+    weekday: Option<u32>,
+    /// `group` also adds the chain up per state group. Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
+
+/// The chain added up per state group. Moves between two states of one group
+/// count on the diagonal.
+#[derive(Serialize)]
+pub struct GroupTransitions {
+    counts: [[u64; GROUP_COUNT]; GROUP_COUNT],
+    probabilities: Vec<Vec<f64>>,
+    sessions: [u64; GROUP_COUNT],
+    /// Mean length of the group's state sessions, not of unbroken runs in it.
+    mean_dwell: Vec<Option<i64>>,
+}
+
+#[derive(Serialize)]
+pub struct TransitionsResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    hour_bucket: u32,
+    hour: Option<u32>,
+    weekday: Option<u32>,
+    /// `counts[a][b]` is how often `b` directly followed `a`.
+    counts: [[u64; STATE_COUNT]; STATE_COUNT],
+    /// `counts` normalised per row, so each visited state's row sums to 1.
+    probabilities: Vec<Vec<f64>>,
+    sessions: [u64; STATE_COUNT],
+    /// Mean session length per state in milliseconds, `null` if none started
+    /// and finished. The entry still running doesn't count.
+    mean_dwell: Vec<Option<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<GroupTransitions>,
+}
+
+/// `counts` normalised per row; rows without any count stay all zero.
+fn row_probabilities<const N: usize>(counts: &[[u64; N]; N]) -> Vec<Vec<f64>> {
+    counts
+        .iter()
+        .map(|row| {
+            let total = row.iter().sum::<u64>();
+            row.iter()
+                .map(|&count| {
+                    if total == 0 {
+                        0.0
+                    } else {
+                        count as f64 / total as f64
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn mean_dwell(sessions: &[u64], dwell: &[i64]) -> Vec<Option<i64>> {
+    sessions
+        .iter()
+        .zip(dwell)
+        .map(|(&sessions, &dwell)| (sessions > 0).then(|| dwell / sessions as i64))
+        .collect()
+}
+
+/// The order-1 Markov chain behind the logged routine: how often each state
+/// follows each other one, optionally within one hour block and/or weekday,
+/// plus how long each state tends to last. Defaults to the last 90 days.
+pub async fn fetch_transitions(
+    Query(params): Query<FetchTransitionsRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchTransitionsRequest {
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        hour_bucket,
+        hour,
+        weekday,
+        group_by,
+    } = params;
+
+    let hour_bucket = hour_bucket.unwrap_or(4);
+    if hour_bucket == 0 || hour_bucket > 24 {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid hour_bucket").into_response();
+    }
+    if hour.is_some_and(|h| h >= 24_u32.div_ceil(hour_bucket)) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid hour").into_response();
+    }
+    if weekday.is_some_and(|w| w >= 7) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid weekday").into_response();
+    }
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 90) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_transitions");
+    let filter = TimeFilter {
+        hour_bucket,
+        hour,
+        weekday,
+    };
+    let stats = transition_stats(&tz, &entries, window.from, window.to, &filter);
+
+    let groups = (group_by == Some(GroupBy::Group)).then(|| {
+        let counts = group_counts(&stats.counts);
+        let sessions = group_totals(&stats.sessions);
+        GroupTransitions {
+            probabilities: row_probabilities(&counts),
+            mean_dwell: mean_dwell(&sessions, &group_totals(&stats.dwell)),
+            counts,
+            sessions,
+        }
+    });
+
+    (
+        StatusCode::OK,
+        Json(TransitionsResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            hour_bucket,
+            hour,
+            weekday,
+            counts: stats.counts,
+            probabilities: row_probabilities(&stats.counts),
+            sessions: stats.sessions,
+            mean_dwell: mean_dwell(&stats.sessions, &stats.dwell),
+            groups,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchDurationsRequest {
+    /// Comma-separated state indices to report on. Defaults to every state.
+    state: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that weekdays and hours are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// Additionally break each state down by the weekday (0 = Monday) or hour
+    /// block its sessions started in.
+    split_by: Option<SplitBy>,
+    /// Width of an hour block when splitting by hour. Defaults to 4.
+    hour_bucket: Option<u32>,
+    /// `group` also reports on every state group, whatever `state` is.
+    /// Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Serialize)]
+pub struct SplitDistribution {
+    key: u32,
+    distribution: Distribution,
+}
+
+#[derive(Serialize)]
+pub struct StateDurations {
+    state: usize,
+    overall: Distribution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<Vec<SplitDistribution>>,
+}
+
+/// Durations of every session of a group's states. Back-to-back sessions of
+/// two states in the group still count separately.
+#[derive(Serialize)]
+pub struct GroupDurations {
+    group: usize,
+    overall: Distribution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<Vec<SplitDistribution>>,
+}
+
+#[derive(Serialize)]
+pub struct DurationsResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    histogram_bins: [&'static str; HISTOGRAM_BINS.len()],
+    states: Vec<StateDurations>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<GroupDurations>>,
+}
+
+/// The overall distribution of `sessions`, and with `split_by` one per key.
+/// `None` without any session.
+fn session_durations(
+    tz: &Tz,
+    sessions: &[&Interval],
+    split_by: Option<SplitBy>,
+    hour_bucket: u32,
+) -> Option<(Distribution, Option<Vec<SplitDistribution>>)> {
+    let lengths: Vec<i64> = sessions.iter().map(|s| s.length()).collect();
+    let overall = Distribution::of(&lengths)?;
+
+    let split = split_by.map(|split_by| {
+        let mut keyed: BTreeMap<u32, Vec<i64>> = BTreeMap::new();
+        for session in sessions {
+            keyed
+                .entry(split_by.key(tz, session.start, hour_bucket))
+                .or_default()
+                .push(session.length());
+        }
+        keyed
+            .into_iter()
+            .filter_map(|(key, lengths)| {
+                Some(SplitDistribution {
+                    key,
+                    distribution: Distribution::of(&lengths)?,
+                })
+            })
+            .collect()
+    });
+
+    Some((overall, split))
+}
+
+/// How long sessions of each state last: count, mean, median, p10/p90, longest
+/// and a log-scale histogram, over finished sessions that started in the window
+/// (defaulting to the last 90 days). States without sessions are left out.
+pub async fn fetch_durations(
+    Query(params): Query<FetchDurationsRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchDurationsRequest {
+        state: states,
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        split_by,
+        hour_bucket,
+        group_by,
+    } = params;
+
+    let states = match states {
+        Some(list) => match parse_states(&list) {
+            Some(states) => states,
+            None => {
+                return (StatusCode::BAD_REQUEST, "Bad request: Invalid state index")
+                    .into_response();
+            }
+        },
+        None => (0..STATE_COUNT).collect(),
+    };
+
+    let hour_bucket = hour_bucket.unwrap_or(4);
+    if hour_bucket == 0 || hour_bucket > 24 {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid hour_bucket").into_response();
+    }
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 90) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_durations");
+    let sessions = finished_sessions_starting_in(&entries, window.from, window.to);
+
+    let report = states
+        .into_iter()
+        .filter_map(|state_id| {
+            let own: Vec<&Interval> = sessions.iter().filter(|s| s.state == state_id).collect();
+            let (overall, split) = session_durations(&tz, &own, split_by, hour_bucket)?;
+            Some(StateDurations {
+                state: state_id,
+                overall,
+                split,
+            })
+        })
+        .collect();
+    let groups = (group_by == Some(GroupBy::Group)).then(|| {
+        ALL_STATE_GROUPS
+            .iter()
+            .enumerate()
+            .filter_map(|(group_id, group)| {
+                let own: Vec<&Interval> = sessions
+                    .iter()
+                    .filter(|s| group.states.contains(&s.state))
+                    .collect();
+                let (overall, split) = session_durations(&tz, &own, split_by, hour_bucket)?;
+                Some(GroupDurations {
+                    group: group_id,
+                    overall,
+                    split,
+                })
+            })
+            .collect()
+    });
+
+    (
+        StatusCode::OK,
+        Json(DurationsResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            histogram_bins: HISTOGRAM_BINS,
+            states: report,
+            groups,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchFocusRequest {
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that days are split in. Defaults to the server's
+    /// `TIMEZONE`.
+    tz: Option<String>,
+    /// Comma-separated focus states. Defaults to the productive group.
+    focus: Option<String>,
+    /// Sessions shorter than this many minutes count as fragments. Defaults to 15.
+    short_minutes: Option<u32>,
+    /// `group` also adds the window's time up per state group. Defaults to
+    /// `state`.
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Serialize)]
+pub struct FocusResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    focus: Vec<usize>,
+    short_minutes: u32,
+    mean_switches_per_day: f64,
+    mean_focus_block: Option<i64>,
+    /// Fraction of tracked time spent in sessions under `short_minutes`.
+    short_block_share: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_totals: Option<[i64; GROUP_COUNT]>,
+    days: Vec<DayFocus>,
+}
+
+const MAX_FOCUS_DAYS: usize = 1_000;
+
+/// How scattered each day was: context switches, average uninterrupted focus
+/// block, and how much time went to fragments shorter than `short_minutes`.
+/// Defaults to the last 14 days.
+pub async fn fetch_focus(
+    Query(params): Query<FetchFocusRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchFocusRequest {
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        focus,
+        short_minutes,
+        group_by,
+    } = params;
+
+    let focus = match focus {
+        Some(list) => match parse_states(&list) {
+            Some(states) => states,
+            None => {
+                return (StatusCode::BAD_REQUEST, "Bad request: Invalid state index")
+                    .into_response();
+            }
+        },
+        None => ALL_STATE_GROUPS[PRODUCTIVE_GROUP_INDEX].states.to_vec(),
+    };
+    let short_minutes = short_minutes.unwrap_or(15);
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 14) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let Some(days) = period_windows(&tz, window.from, window.to, Period::Day, MAX_FOCUS_DAYS)
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Too many days").into_response();
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_focus");
+    let days = focus_by_day(
+        &entries,
+        &days,
+        now,
+        &focus,
+        i64::from(short_minutes) * 60_000,
+    );
+
+    // Days that haven't started yet would only drag the averages down.
+    let elapsed: Vec<&DayFocus> = days.iter().filter(|day| day.from < now).collect();
+    let switches = elapsed.iter().map(|day| day.switches).sum::<u64>();
+    let blocks = elapsed.iter().map(|day| day.focus_blocks).sum::<u64>();
+    let focus_time = elapsed.iter().map(|day| day.focus_time).sum::<i64>();
+    let short_time = elapsed.iter().map(|day| day.short_block_time).sum::<i64>();
+    let tracked = elapsed.iter().map(|day| day.tracked).sum::<i64>();
+    let group_totals = (group_by == Some(GroupBy::Group)).then(|| {
+        let intervals = clip_intervals(&entries, window.from, window.to, now);
+        group_totals(&state_totals(&intervals))
+    });
+
+    (
+        StatusCode::OK,
+        Json(FocusResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            focus,
+            short_minutes,
+            mean_switches_per_day: switches as f64 / elapsed.len().max(1) as f64,
+            mean_focus_block: (blocks > 0).then(|| focus_time / blocks as i64),
+            short_block_share: if tracked == 0 {
+                0.0
+            } else {
+                short_time as f64 / tracked as f64
+            },
+            group_totals,
+            days,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchSleepRequest {
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that nights and times of day are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// Nightly sleep target in hours, for the debt. Defaults to 8.
+    target_hours: Option<f64>,
+    /// Wake-ups up to this many minutes don't end an episode. Defaults to 30.
+    merge_minutes: Option<u32>,
+    /// How many days the rolling average spans. Defaults to 7.
+    rolling_days: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct SleepNight {
+    /// Local date of waking up.
+    date: String,
+    bedtime: i64,
+    wake: i64,
+    bedtime_local: String,
+    wake_local: String,
+    /// Time asleep in the main episode, wake-ups excluded.
+    duration: i64,
+    interruptions: u32,
+    naps: usize,
+    nap_time: i64,
+    /// Mean main-episode duration over this and the preceding nights within
+    /// `rolling_days`.
+    rolling_average: i64,
+    /// Cumulative shortfall against the target up to and including this night,
+    /// naps counted, and days without any sleep counted as a full target
+    /// short. Negative means surplus.
+    debt: i64,
+}
+
+#[derive(Serialize)]
+pub struct SleepResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    target: i64,
+    mean_duration: Option<i64>,
+    mean_bedtime: Option<String>,
+    mean_wake: Option<String>,
+    /// Sleep Regularity Index, 100 for a perfectly fixed schedule. `null` for
+    /// windows shorter than a day.
+    regularity_index: Option<f64>,
+    debt: i64,
+    /// Whole days that are over, since logging began, without a night waking
+    /// up in them. Each adds the whole target to the debt.
+    missed_nights: Vec<String>,
+    nights: Vec<SleepNight>,
+}
+
+const SRI_SAMPLE_MS: i64 = 5 * 60 * 1000;
+const DEFAULT_MERGE_MINUTES: u32 = 30;
+const MAX_SLEEP_DAYS: usize = 1_000;
+
+fn format_minutes(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Per-night sleep: the main episode's bedtime, wake time and duration (short
+/// wake-ups merged, naps counted separately), with rolling averages, the Sleep
+/// Regularity Index and cumulative debt against a target. Defaults to the last
+/// 30 days.
+pub async fn fetch_sleep(
+    Query(params): Query<FetchSleepRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchSleepRequest {
+        from,
+        to,
+        period,
+        offset,
+        tz,
+        target_hours,
+        merge_minutes,
+        rolling_days,
+    } = params;
+
+    let target_hours = target_hours.unwrap_or(8.0);
+    if !(0.0..=24.0).contains(&target_hours) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid target_hours").into_response();
+    }
+    let target = (target_hours * 3_600_000.0) as i64;
+    let merge_gap = i64::from(merge_minutes.unwrap_or(DEFAULT_MERGE_MINUTES)) * 60_000;
+    let rolling_days = i64::from(rolling_days.unwrap_or(7).max(1));
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 30) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let Some(days) = period_windows(&tz, window.from, window.to, Period::Day, MAX_SLEEP_DAYS)
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Too many days").into_response();
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_sleep");
+    let episodes = sleep_episodes(
+        &entries,
+        SLEEP_STATE_INDEX,
+        window.from,
+        window.to,
+        now,
+        merge_gap,
+    );
+    let nights = group_nights(&tz, &episodes);
+
+    // Whole days that are over, since the first entry, with no night ending in
+    // them. Nothing was logged before the first entry, so those aren't missed,
+    // and a window starting mid-day may have cut off that day's night.
+    let logging_since = entries.first().map_or(i64::MAX, |&(_, start)| start);
+    let missed: Vec<NaiveDate> = days
+        .iter()
+        .filter(|day| day.from >= window.from && day.to <= now && day.to > logging_since)
+        .filter_map(|day| local_date(&tz, day.from))
+        .filter(|date| nights.binary_search_by_key(date, |n| n.date).is_err())
+        .collect();
+
+    let mut debt = 0i64;
+    let mut missed_so_far = 0;
+    let mut report = Vec::with_capacity(nights.len());
+    for (i, night) in nights.iter().enumerate() {
+        while missed
+            .get(missed_so_far)
+            .is_some_and(|&date| date < night.date)
+        {
+            missed_so_far += 1;
+            debt += target;
+        }
+        let nap_time = night.naps.iter().map(|nap| nap.asleep).sum::<i64>();
+        debt += target - night.main.asleep - nap_time;
+
+        let recent: Vec<i64> = nights[..=i]
+            .iter()
+            .rev()
+            .take_while(|n| (night.date - n.date).num_days() < rolling_days)
+            .map(|n| n.main.asleep)
+            .collect();
+
+        report.push(SleepNight {
+            date: night.date.format("%Y-%m-%d").to_string(),
+            bedtime: night.main.start,
+            wake: night.main.end,
+            bedtime_local: minutes_into_day(&tz, night.main.start)
+                .map(format_minutes)
+                .unwrap_or_default(),
+            wake_local: minutes_into_day(&tz, night.main.end)
+                .map(format_minutes)
+                .unwrap_or_default(),
+            duration: night.main.asleep,
+            interruptions: night.main.interruptions,
+            naps: night.naps.len(),
+            nap_time,
+            rolling_average: recent.iter().sum::<i64>() / recent.len() as i64,
+            debt,
+        });
+    }
+
+    let bedtimes: Vec<u32> = nights
+        .iter()
+        .filter_map(|n| minutes_into_day(&tz, n.main.start))
+        .collect();
+    let wakes: Vec<u32> = nights
+        .iter()
+        .filter_map(|n| minutes_into_day(&tz, n.main.end))
+        .collect();
+    let mean_duration = (!nights.is_empty())
+        .then(|| nights.iter().map(|n| n.main.asleep).sum::<i64>() / nights.len() as i64);
+
+    let sleep: Vec<Interval> = clip_intervals(&entries, window.from, window.to, now)
+        .into_iter()
+        .filter(|interval| interval.state == SLEEP_STATE_INDEX)
+        .collect();
+    let regularity_index =
+        sleep_regularity_index(&sleep, window.from, window.to.min(now), SRI_SAMPLE_MS);
+    debt += target * (missed.len() - missed_so_far) as i64;
+
+    (
+        StatusCode::OK,
+        Json(SleepResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            target,
+            mean_duration,
+            mean_bedtime: mean_time_of_day(&bedtimes).map(format_minutes),
+            mean_wake: mean_time_of_day(&wakes).map(format_minutes),
+            regularity_index,
+            debt,
+            missed_nights: missed
+                .iter()
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .collect(),
+            nights: report,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+pub struct HabitEntry {
+    habit_id: u64,
+    #[serde(flatten)]
+    habit: Habit,
+}
+
+/// Big-endian, unlike the events tree, so habits list in id order.
+fn habit_key(habit_id: u64) -> [u8; 8] {
+    habit_id.to_be_bytes()
+}
+
+fn read_habit(habits: &sled::Tree, habit_id: u64) -> Result<Option<Habit>, String> {
+    let Some(bytes) = habits
+        .get(habit_key(habit_id))
+        .map_err(|err| err.to_string())?
+    else {
+        return Ok(None);
+    };
+    serde_json::from_slice(&bytes)
+        .map(Some)
+        .map_err(|err| format!("corrupted habit {habit_id}: {err}"))
+}
+
+fn write_habit(habits: &sled::Tree, habit_id: u64, habit: &Habit) -> Result<(), String> {
+    let bytes = serde_json::to_vec(habit).map_err(|err| err.to_string())?;
+    habits
+        .insert(habit_key(habit_id), bytes)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn read_all_habits(habits: &sled::Tree) -> Vec<HabitEntry> {
+    habits
+        .iter()
+        .filter_map(|item| item.ok())
+        .filter_map(|(key, value)| {
+            let habit_id = u64::from_be_bytes(key.as_ref().try_into().ok()?);
+            match serde_json::from_slice(&value) {
+                Ok(habit) => Some(HabitEntry { habit_id, habit }),
+                Err(err) => {
+                    eprintln!("read_all_habits: skipping corrupted habit {habit_id}: {err}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub async fn list_habits(State(state): State<AppState>) -> Response {
+    (StatusCode::OK, Json(read_all_habits(&state.habits))).into_response()
+}
+
+pub async fn create_habit(State(state): State<AppState>, Json(habit): Json<Habit>) -> Response {
+    if let Err(err) = habit.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+
+    // Ids are never reused, so a client holding a deleted id can't end up
+    // editing an unrelated habit.
+    let habit_id = match state.meta.update_and_fetch(b"habit_next_id", |old| {
+        Some(to_ivec(old.map_or(0, |v| ivec_to_u64(IVec::from(v))) + 1))
+    }) {
+        Ok(Some(next)) => ivec_to_u64(next) - 1,
+        Ok(None) => unreachable!(),
+        Err(err) => {
+            println!("{err:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+        }
+    };
+
+    if let Err(err) = write_habit(&state.habits, habit_id, &habit) {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+    }
+
+    (StatusCode::OK, Json(HabitEntry { habit_id, habit })).into_response()
+}
+
+pub async fn get_habit(Path(habit_id): Path<u64>, State(state): State<AppState>) -> Response {
+    match read_habit(&state.habits, habit_id) {
+        Ok(Some(habit)) => (StatusCode::OK, Json(HabitEntry { habit_id, habit })).into_response(),
+        Ok(None) => (StatusCode::BAD_REQUEST, "Bad request: Habit not found").into_response(),
+        Err(err) => {
+            println!("{err:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, err).into_response()
+        }
+    }
+}
+
+pub async fn update_habit(
+    Path(habit_id): Path<u64>,
+    State(state): State<AppState>,
+    Json(habit): Json<Habit>,
+) -> Response {
+    if let Err(err) = habit.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+
+    let bytes = match serde_json::to_vec(&habit) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("{err:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+        }
+    };
+    // Replace only if it still exists, in one step, so an update racing a
+    // delete can't bring the habit back.
+    match state
+        .habits
+        .update_and_fetch(habit_key(habit_id), |old| old.map(|_| bytes.clone()))
+    {
+        Ok(Some(_)) => (StatusCode::OK, Json(HabitEntry { habit_id, habit })).into_response(),
+        Ok(None) => (StatusCode::BAD_REQUEST, "Bad request: Habit not found").into_response(),
+        Err(err) => {
+            println!("{err:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response()
+        }
+    }
+}
+
+pub async fn delete_habit(Path(habit_id): Path<u64>, State(state): State<AppState>) -> Response {
+    match state.habits.remove(habit_key(habit_id)) {
+        Ok(Some(_)) => (StatusCode::OK, Json(habit_id)).into_response(),
+        Ok(None) => (StatusCode::BAD_REQUEST, "Bad request: Habit not found").into_response(),
+        Err(err) => {
+            println!("{err:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FetchHabitStatusRequest {
+    /// Only report on this habit. Defaults to all of them.
+    habit_id: Option<u64>,
+    from: Option<i64>,
+    to: Option<i64>,
+    period: Option<Period>,
+    offset: Option<i32>,
+    /// IANA timezone that days and cut-off times are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HabitStatus {
+    habit_id: u64,
+    name: String,
+    #[serde(flatten)]
+    streaks: Streaks,
+    passed: usize,
+    failed: usize,
+    days: Vec<HabitDay>,
+}
+
+#[derive(Serialize)]
+pub struct HabitStatusResponse {
+    from: i64,
+    to: i64,
+    label: String,
+    habits: Vec<HabitStatus>,
+}
+
+const MAX_HABIT_DAYS: usize = 1_000;
+
+/// Per-day pass/fail of every habit (or one), with current and longest streaks
+/// over the window. Defaults to the last 90 days; days that haven't started yet,
+/// or ended before the first entry, are left out.
+pub async fn fetch_habit_status(
+    Query(params): Query<FetchHabitStatusRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchHabitStatusRequest {
+        habit_id,
+        from,
+        to,
+        period,
+        offset,
+        tz,
+    } = params;
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, from, to, period, offset, now, 90) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let Some(mut days) = period_windows(&tz, window.from, window.to, Period::Day, MAX_HABIT_DAYS)
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Too many days").into_response();
+    };
+    days.retain(|day| day.from <= now);
+
+    let habits = match habit_id {
+        Some(habit_id) => match read_habit(&state.habits, habit_id) {
+            Ok(Some(habit)) => vec![HabitEntry { habit_id, habit }],
+            Ok(None) => {
+                return (StatusCode::BAD_REQUEST, "Bad request: Habit not found").into_response();
+            }
+            Err(err) => {
+                println!("{err:?}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+            }
+        },
+        None => read_all_habits(&state.habits),
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_habit_status");
+    let intervals = match (days.first(), days.last()) {
+        (Some(first), Some(last)) => clip_intervals(&entries, first.from, last.to, now),
+        _ => Vec::new(),
+    };
+
+    let habits = habits
+        .into_iter()
+        .map(|HabitEntry { habit_id, habit }| {
+            let days = evaluate_habit(&habit, &tz, &intervals, &days, now);
+            HabitStatus {
+                habit_id,
+                name: habit.name,
+                streaks: streaks(&days),
+                passed: days.iter().filter(|d| d.outcome == Outcome::Pass).count(),
+                failed: days.iter().filter(|d| d.outcome == Outcome::Fail).count(),
+                days,
+            }
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(HabitStatusResponse {
+            from: window.from,
+            to: window.to,
+            label: window.label,
+            habits,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn fetch_length(State(state): State<AppState>) -> Response {
+    let length = get_length(&state.meta);
+
+    (StatusCode::OK, Json(length)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ForceSetLengthRequest {
+    new_length: u64,
+}
+
+pub async fn force_set_length(
+    State(state): State<AppState>,
+    Json(payload): Json<ForceSetLengthRequest>,
+) -> Response {
+    let ForceSetLengthRequest { new_length } = payload;
+
+    let result: TransactionResult<(), sled::Error> = state.meta.transaction(|tx_meta| {
+        tx_meta.insert(b"len", to_ivec(new_length))?;
+        bump_history_version(tx_meta)?;
+        Ok(())
+    });
+    if let Err(err) = result {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+    }
+
+    (StatusCode::OK, Json(new_length)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SuggestRequest {
+    /// How many activities to suggest. Defaults to 3.
+    limit: Option<usize>,
+    /// IANA timezone that weekday and hour-of-day context is derived in.
+    /// Defaults to the server's `TIMEZONE`.
+    tz: Option<String>,
+    /// A fixed UTC offset in minutes, east of Greenwich, for clients that
+    /// predate `tz`. Unlike a named zone it doesn't follow DST, so patterns
+    /// drift by an hour for half the year. Cannot be combined with `tz`.
+    tz_offset: Option<i32>,
+    /// Moment to predict for, in epoch milliseconds. Defaults to now.
+    at: Option<i64>,
+    /// State to predict away from. Defaults to the latest recorded state, and
+    /// is never itself suggested.
+    current_state: Option<u8>,
+    /// How many of the most recent entries to train on. Defaults to the
+    /// configuration's `maximum_training_entries`; any other value trains a
+    /// predictor for this request alone, which is slow on a long log.
+    max_entries: Option<usize>,
+    /// Whether to include each suggestion's raw fused score alongside its
+    /// probability. Defaults to false.
+    scores: Option<bool>,
+    /// Whether to break each suggestion's score down by the tables and prior
+    /// behind it. Defaults to false.
+    explain: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct Suggestion {
+    state: u8,
+    name: &'static str,
+    emoji: &'static str,
+    colour: &'static str,
+    /// How likely this state is to come next, out of every state but the
+    /// current one.
+    probability: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<Explanation>,
+}
+
+#[derive(Serialize)]
+pub struct SuggestResponse {
+    at: i64,
+    /// The zone the time-of-day context was taken in.
+    tz: String,
+    current_state: Option<u8>,
+    trained_on: usize,
+    suggestions: Vec<Suggestion>,
+}
+
+const MAX_TZ_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// The zone a predictor request asked for: a named `tz`, a fixed `tz_offset`
+/// in minutes, or the server default, but never both.
+fn parse_zone(tz: Option<String>, tz_offset: Option<i32>) -> Result<Zone, &'static str> {
+    match (tz, tz_offset) {
+        (Some(_), Some(_)) => Err("Bad request: tz cannot be combined with tz_offset"),
+        (tz, None) => parse_timezone(tz.as_deref())
+            .map(Zone::Named)
+            .ok_or("Bad request: Invalid tz"),
+        (None, Some(tz_offset)) => (tz_offset.abs() <= MAX_TZ_OFFSET_MINUTES)
+            .then(|| FixedOffset::east_opt(tz_offset * 60))
+            .flatten()
+            .map(Zone::Fixed)
+            .ok_or("Bad request: Invalid tz_offset"),
+    }
+}
+
+/// Suggests the activities most likely to come next, using the TAGE predictor in
+/// [`crate::predictor`]. Predictors are kept trained in
+/// [`crate::predictor_cache`], which keeps the clients stateless — they no
+/// longer need to download the whole history to predict locally.
+pub async fn suggest_next_states(
+    Query(params): Query<SuggestRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let SuggestRequest {
+        limit,
+        tz,
+        tz_offset,
+        at: requested_at,
+        current_state,
+        max_entries,
+        scores,
+        explain,
+    } = params;
+
+    let limit = limit.unwrap_or(3);
+    if limit == 0 || limit > STATE_COUNT {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid limit").into_response();
+    }
+
+    let zone = match parse_zone(tz, tz_offset) {
+        Ok(zone) => zone,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let at = requested_at.unwrap_or_else(|| Utc::now().timestamp_millis());
+    if !is_valid_timestamp(at) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid timestamp").into_response();
+    }
+
+    if current_state.is_some_and(|s| s as usize >= STATE_COUNT) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid state index").into_response();
+    }
+
+    let configuration = get_predictor_configuration(&state.meta);
+    // Kept out of the cache, where each value would evict the live predictor.
+    let one_off = max_entries.is_some_and(|n| n != configuration.maximum_training_entries);
+    let max_entries = max_entries.unwrap_or(configuration.maximum_training_entries);
+    if max_entries == 0 || max_entries > MAX_TRAINING_ENTRIES {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid max_entries").into_response();
+    }
+
+    // The first request for a zone and configuration trains over up to tens of
+    // thousands of entries, which is CPU-bound, so keep it off the async
+    // runtime's worker threads. Later ones only catch up on new entries.
+    let predicted = tokio::task::spawn_blocking(move || {
+        let length = get_length(&state.meta);
+        let latest = length
+            .checked_sub(1)
+            .and_then(|i| try_read_from_value(&state.events, i))
+            .map(|(state_id, _)| state_id)
+            .filter(|&state_id| usize::from(state_id) < STATE_COUNT);
+        // Only the configured predictor's suggestions for the real present are
+        // worth scoring against the entry that follows, not what-ifs for another
+        // time, state or training window.
+        let live =
+            requested_at.is_none() && current_state.is_none_or(|s| Some(s) == latest) && !one_off;
+        let current_state = current_state.or(latest);
+
+        let configuration = Configuration {
+            maximum_training_entries: max_entries,
+            ..configuration
+        };
+        let current = current_state.map(|s| s as usize);
+        let suggest = |predictor: &ActivityPredictor<Zone>, trained_on| {
+            let mut predictions = predictor.scored_predictions(at, current, limit.max(3));
+            let ranked: Vec<usize> = predictions.iter().map(|p| p.state_id).collect();
+            predictions.truncate(limit);
+            let explanations = if explain.unwrap_or(false) {
+                let state_ids: Vec<usize> = predictions.iter().map(|p| p.state_id).collect();
+                predictor.explanations(at, current, &state_ids)
+            } else {
+                Vec::new()
+            };
+            (ranked, trained_on, predictions, explanations)
+        };
+        let (ranked, trained_on, predictions, explanations) = if one_off {
+            with_uncached_predictor(zone, configuration, &state.events, &state.meta, suggest)
+        } else {
+            state.predictors.with_predictor(
+                zone,
+                configuration,
+                &state.events,
+                &state.meta,
+                suggest,
+            )
+        };
+
+        if live {
+            // Ranked just as deep as the live one, so both are judged alike.
+            // Training the challenger from scratch would hold this suggestion
+            // up, so that happens in the background and it sits this one out.
+            let challenger = get_predictor_challenger(&state.meta).and_then(|challenger| {
+                let configuration = &challenger.configuration;
+                if !state
+                    .predictors
+                    .is_trained(zone, configuration, &state.meta)
+                {
+                    warm_predictor(state.clone(), zone, challenger.configuration);
+                    return None;
+                }
+                Some(state.predictors.with_predictor(
+                    zone,
+                    challenger.configuration,
+                    &state.events,
+                    &state.meta,
+                    |predictor, _| {
+                        ShadowSuggestion {
+                            since: challenger.since,
+                            states: predictor
+                                .scored_predictions(at, current, ranked.len().max(3))
+                                .into_iter()
+                                .map(|p| p.state_id)
+                                .collect(),
+                        }
+                    },
+                ))
+            });
+            remember_suggestion(
+                &state.meta,
+                &PendingSuggestion {
+                    length,
+                    history_version: get_history_version(&state.meta),
+                    states: ranked,
+                    challenger,
+                },
+            );
+        }
+        (current_state, trained_on, predictions, explanations)
+    })
+    .await;
+
+    let (current_state, trained_on, predictions, explanations) = match predicted {
+        Ok(result) => result,
+        Err(err) => {
+            println!("{err:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+        }
+    };
+
+    let with_scores = scores.unwrap_or(false);
+    let mut explanations = explanations.into_iter();
+    let suggestions = predictions
+        .into_iter()
+        .map(|prediction| {
+            let detail = ALL_STATES_DETAILS[prediction.state_id];
+            Suggestion {
+                state: prediction.state_id as u8,
+                name: detail.name,
+                emoji: detail.emoji,
+                colour: detail.colour,
+                probability: prediction.probability,
+                score: with_scores.then_some(prediction.score),
+                explanation: explanations.next(),
+            }
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(SuggestResponse {
+            at,
+            tz: zone.name(),
+            current_state,
+            trained_on,
+            suggestions,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SuggestDurationRequest {
+    /// IANA timezone that the hour-of-day context is derived in. Defaults to
+    /// the server's `TIMEZONE`.
+    tz: Option<String>,
+    /// A fixed UTC offset in minutes, as for `/api/suggest`. Cannot be combined
+    /// with `tz`.
+    tz_offset: Option<i32>,
+    /// Moment the current entry is still running at, in epoch milliseconds.
+    /// Defaults to now.
+    at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct EndEstimate {
+    expected_end: i64,
+    /// Half of comparable past runs ended between these two times.
+    end_p25: i64,
+    end_p75: i64,
+    samples: usize,
+    context: DurationContext,
+}
+
+#[derive(Serialize)]
+pub struct SuggestDurationResponse {
+    at: i64,
+    tz: String,
+    /// The latest entry, which is taken to still be running. `None` for an
+    /// empty log.
+    current_state: Option<u8>,
+    started_at: Option<i64>,
+    trained_on: usize,
+    /// `None` when the current state has never lasted this long before.
+    estimate: Option<EndEstimate>,
+}
+
+/// Predicts when the latest entry will end, from how long the same state has
+/// lasted before at the same time of day and after the same state. Only past
+/// runs that outlasted the time already elapsed are counted.
+pub async fn suggest_duration(
+    Query(params): Query<SuggestDurationRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let SuggestDurationRequest { tz, tz_offset, at } = params;
+
+    let zone = match parse_zone(tz, tz_offset) {
+        Ok(zone) => zone,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let at = at.unwrap_or_else(|| Utc::now().timestamp_millis());
+    if !is_valid_timestamp(at) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid timestamp").into_response();
+    }
+
+    let predicted = tokio::task::spawn_blocking(move || {
+        state.predictors.with_predictor(
+            zone,
+            get_predictor_configuration(&state.meta),
+            &state.events,
+            &state.meta,
+            |predictor, trained_on| {
+                let open = predictor.open_entry();
+                let estimate = open.and_then(|open| {
+                    predictor.duration_estimate(
+                        open.state_id,
+                        open.started_at,
+                        open.previous_state_id,
+                        at.max(open.started_at),
+                    )
+                });
+                (open, trained_on, estimate)
+            },
+        )
+    })
+    .await;
+
+    let (open, trained_on, estimate) = match predicted {
+        Ok(result) => result,
+        Err(err) => {
+            println!("{err:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+        }
+    };
+
+    let estimate = open.zip(estimate).map(|(open, estimate)| EndEstimate {
+        expected_end: open.started_at + estimate.median,
+        end_p25: open.started_at + estimate.p25,
+        end_p75: open.started_at + estimate.p75,
+        samples: estimate.samples,
+        context: estimate.context,
+    });
+
+    (
+        StatusCode::OK,
+        Json(SuggestDurationResponse {
+            at,
+            tz: zone.name(),
+            current_state: open.map(|open| open.state_id as u8),
+            started_at: open.map(|open| open.started_at),
+            trained_on,
+            estimate,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchForecastRequest {
+    /// End of the forecast in epoch milliseconds, at most a week ahead.
+    /// Defaults to the coming local midnight.
+    until: Option<i64>,
+    /// IANA timezone for time-of-day context, the default `until` and `totals`.
+    /// Defaults to the server's `TIMEZONE`.
+    tz: Option<String>,
+    /// Also project per-state totals for the current calendar period, logged
+    /// so far plus forecast up to `until`.
+    totals: Option<Period>,
+}
+
+#[derive(Serialize)]
+pub struct ScheduledBlock {
+    state: u8,
+    name: &'static str,
+    start: i64,
+    end: i64,
+    /// How likely this state is to follow the previous block. `None` for the
+    /// entry already running.
+    probability: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ProjectedTotals {
+    period: Period,
+    label: String,
+    from: i64,
+    to: i64,
+    /// How far the projection reaches: `until` or the end of the period,
+    /// whichever is first.
+    through: i64,
+    logged: [i64; STATE_COUNT],
+    forecast: [i64; STATE_COUNT],
+    totals: [i64; STATE_COUNT],
+}
+
+#[derive(Serialize)]
+pub struct ForecastResponse {
+    at: i64,
+    until: i64,
+    tz: String,
+    trained_on: usize,
+    blocks: Vec<ScheduledBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    projected: Option<ProjectedTotals>,
+}
+
+const MAX_FORECAST_MILLISECONDS: i64 = 7 * 24 * 3600 * 1000;
+const MAX_FORECAST_BLOCKS: usize = 500;
+
+/// Rolls the predictor forward from the latest entry to `until`, taking the
+/// most likely next state at each step and its median duration. Early blocks
+/// are a reasonable guess; hours out it is closer to a typical day than a plan.
+pub async fn fetch_forecast(
+    Query(params): Query<FetchForecastRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchForecastRequest { until, tz, totals } = params;
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let until = match until {
+        Some(until) => until,
+        None => match calendar_window(&tz, Period::Day, 0, now) {
+            Some(today) => today.to,
+            None => {
+                return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+            }
+        },
+    };
+    if until <= now || until - now > MAX_FORECAST_MILLISECONDS {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid until").into_response();
+    }
+
+    let window = match totals.map(|period| (period, calendar_window(&tz, period, 0, now))) {
+        None => None,
+        Some((period, Some(window))) => Some((period, window)),
+        Some((_, None)) => {
+            return (StatusCode::BAD_REQUEST, "Bad request: Invalid totals").into_response();
+        }
+    };
+
+    let events = state.events.clone();
+    let meta = state.meta.clone();
+    let predicted = tokio::task::spawn_blocking(move || {
+        state.predictors.with_predictor(
+            Zone::Named(tz),
+            get_predictor_configuration(&state.meta),
+            &state.events,
+            &state.meta,
+            |predictor, trained_on| {
+                (
+                    trained_on,
+                    predictor.forecast(now, until, MAX_FORECAST_BLOCKS),
+                )
+            },
+        )
+    })
+    .await;
+
+    let (trained_on, blocks) = match predicted {
+        Ok(result) => result,
+        Err(err) => {
+            println!("{err:?}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err}")).into_response();
+        }
+    };
+
+    let projected = window.map(|(period, window)| {
+        let entries = read_valid_entries(&events, &meta, "fetch_forecast");
+        let logged = state_totals(&clip_intervals(
+            &entries,
+            window.from,
+            window.to.min(now),
+            now,
+        ));
+        // Only the part of each block still to come; the rest is logged.
+        let mut forecast = [0i64; STATE_COUNT];
+        for block in &blocks {
+            let start = block.start.max(now).max(window.from);
+            let end = block.end.min(window.to);
+            if start < end {
+                forecast[block.state_id] += end - start;
+            }
+        }
+        let mut totals = logged;
+        for (total, extra) in totals.iter_mut().zip(forecast) {
+            *total += extra;
+        }
+        ProjectedTotals {
+            period,
+            label: window.label,
+            from: window.from,
+            to: window.to,
+            through: until.min(window.to),
+            logged,
+            forecast,
+            totals,
+        }
+    });
+
+    let blocks = blocks
+        .into_iter()
+        .map(|block| ScheduledBlock {
+            state: block.state_id as u8,
+            name: ALL_STATES_DETAILS[block.state_id].name,
+            start: block.start,
+            end: block.end,
+            probability: block.probability,
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(ForecastResponse {
+            at: now,
+            until,
+            tz: tz.name().to_string(),
+            trained_on,
+            blocks,
+            projected,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct UpdatePredictorConfigRequest {
+    /// Switch to a named configuration wholesale.
+    preset: Option<Preset>,
+    /// Or set one field by field; fields left out take their default values.
+    configuration: Option<Configuration>,
+}
+
+#[derive(Serialize)]
+pub struct PredictorConfigResponse {
+    /// The preset the configuration matches exactly, if any.
+    preset: Option<Preset>,
+    configuration: Configuration,
+}
+
+pub async fn get_predictor_config(State(state): State<AppState>) -> Response {
+    let configuration = get_predictor_configuration(&state.meta);
+    (
+        StatusCode::OK,
+        Json(PredictorConfigResponse {
+            preset: configuration.preset(),
+            configuration,
+        }),
+    )
+        .into_response()
+}
+
+/// Replaces the configuration every predictor endpoint uses. Predictors for the
+/// old one are left to age out of the cache, so switching back is free.
+pub async fn update_predictor_config(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdatePredictorConfigRequest>,
+) -> Response {
+    let configuration = match (payload.preset, payload.configuration) {
+        (Some(preset), None) => preset.configuration(),
+        (None, Some(configuration)) => configuration,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Bad request: Give exactly one of preset and configuration",
+            )
+                .into_response();
+        }
+    };
+    if let Err(err) = configuration.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+
+    if let Err(err) = set_predictor_configuration(&state.meta, &configuration) {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+    }
+
+    (
+        StatusCode::OK,
+        Json(PredictorConfigResponse {
+            preset: configuration.preset(),
+            configuration,
+        }),
+    )
+        .into_response()
+}
+
+/// Trains the predictor for `zone` and `configuration` in the background, so no
+/// request has to wait for it.
+fn warm_predictor(state: AppState, zone: Zone, configuration: Configuration) {
+    tokio::task::spawn_blocking(move || {
+        state
+            .predictors
+            .with_predictor(zone, configuration, &state.events, &state.meta, |_, _| ());
+    });
+}
+
+fn remember_suggestion(meta: &sled::Tree, pending: &PendingSuggestion) {
+    let stored = serde_json::to_vec(pending)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| {
+            meta.insert(b"pending_suggestion", bytes)
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = stored {
+        eprintln!("remember_suggestion: {err}");
+    }
+}
+
+/// Scores the entry just added at `entry_idx` against the suggestion made for
+/// it, if there was one. Losing an outcome only thins the metrics, so failures
+/// are logged rather than failing the add.
+fn score_pending_suggestion(state: &AppState, entry_idx: u64, new_state: usize, at: i64) {
+    let pending = match state.meta.remove(b"pending_suggestion") {
+        Ok(Some(bytes)) => serde_json::from_slice::<PendingSuggestion>(&bytes).ok(),
+        Ok(None) => None,
+        Err(err) => {
+            eprintln!("score_pending_suggestion: {err}");
+            None
+        }
+    };
+    let Some(pending) = pending.filter(|pending| {
+        pending.length == entry_idx && pending.history_version == get_history_version(&state.meta)
+    }) else {
+        return;
+    };
+
+    let outcome = pending.score(new_state, at);
+    let stored = serde_json::to_vec(&outcome)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| {
+            state
+                .outcomes
+                .insert(outcome_key(at, entry_idx), bytes)
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = stored {
+        eprintln!("score_pending_suggestion: {err}");
+    }
+}
+
+/// Big-endian, unlike the other trees, so outcomes iterate oldest first and the
+/// latest can be read off the end.
+fn outcome_key(at: i64, entry_idx: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16);
+    key.extend_from_slice(&at.to_be_bytes());
+    key.extend_from_slice(&entry_idx.to_be_bytes());
+    key
+}
+
+/// Up to `count` of the latest outcomes that `keep` accepts, oldest first.
+fn read_latest_outcomes(
+    outcomes: &sled::Tree,
+    count: usize,
+    keep: impl Fn(&ScoredTransition) -> bool,
+) -> Vec<ScoredTransition> {
+    let mut latest: Vec<ScoredTransition> = outcomes
+        .iter()
+        .rev()
+        .filter_map(|item| item.ok())
+        .filter_map(|(_, value)| match serde_json::from_slice(&value) {
+            Ok(outcome) => Some(outcome),
+            Err(err) => {
+                eprintln!("read_latest_outcomes: skipping corrupted outcome: {err}");
+                None
+            }
+        })
+        .filter(|outcome: &ScoredTransition| outcome.state < STATE_COUNT && keep(outcome))
+        .take(count)
+        .collect();
+    latest.reverse();
+    latest
+}
+
+#[derive(Deserialize)]
+pub struct FetchPredictorMetricsRequest {
+    /// How many of the latest scored transitions to rate. Defaults to 200.
+    last: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct StateAccuracy {
+    state: u8,
+    name: &'static str,
+    #[serde(flatten)]
+    accuracy: Accuracy,
+}
+
+#[derive(Serialize)]
+pub struct PredictorMetricsResponse {
+    /// When the oldest transition rated was logged.
+    since: Option<i64>,
+    latest: Accuracy,
+    /// The `last` transitions before those, to compare against. `None` until
+    /// there are any.
+    previous: Option<Accuracy>,
+    /// Per state actually started, over the latest transitions. States never
+    /// started in them are left out.
+    states: Vec<StateAccuracy>,
+}
+
+const MAX_METRICS_TRANSITIONS: usize = 10_000;
+
+/// Rolling accuracy of live suggestions: of the transitions that followed a
+/// `/api/suggest` call, how often the new state was suggested first, in the
+/// top three, and the mean reciprocal rank.
+pub async fn fetch_predictor_metrics(
+    Query(params): Query<FetchPredictorMetricsRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let last = params.last.unwrap_or(200);
+    if last == 0 || last > MAX_METRICS_TRANSITIONS {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid last").into_response();
+    }
+
+    let outcomes = read_latest_outcomes(&state.outcomes, last * 2, |_| true);
+    let (previous, latest) = outcomes.split_at(outcomes.len().saturating_sub(last));
+
+    let states = accuracy_by_state(latest)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, accuracy)| accuracy.transitions > 0)
+        .map(|(state_id, accuracy)| StateAccuracy {
+            state: state_id as u8,
+            name: ALL_STATES_DETAILS[state_id].name,
+            accuracy,
+        })
+        .collect();
+
+    (
+        StatusCode::OK,
+        Json(PredictorMetricsResponse {
+            since: latest.first().map(|outcome| outcome.at),
+            latest: accuracy(latest),
+            previous: (!previous.is_empty()).then(|| accuracy(previous)),
+            states,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchPredictorChallengerRequest {
+    /// How many of the latest transitions scored for the challenger to compare
+    /// on. Defaults to 200.
+    last: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct PredictorChallengerResponse {
+    challenger: Option<Challenger>,
+    /// The preset the challenger matches, if any.
+    preset: Option<Preset>,
+    /// Live against challenger over the same transitions. `None` while there
+    /// is no challenger.
+    comparison: Option<Comparison>,
+}
+
+fn challenger_response(meta: &sled::Tree, outcomes: &sled::Tree, last: usize) -> Response {
+    let challenger = get_predictor_challenger(meta);
+    let comparison = challenger.as_ref().map(|challenger| {
+        let outcomes = read_latest_outcomes(outcomes, last, |outcome| {
+            outcome
+                .challenger
+                .is_some_and(|shadow| shadow.since == challenger.since)
+        });
+        compare(&outcomes, challenger.since)
+    });
+    (
+        StatusCode::OK,
+        Json(PredictorChallengerResponse {
+            preset: challenger
+                .as_ref()
+                .and_then(|challenger| challenger.configuration.preset()),
+            challenger,
+            comparison,
+        }),
+    )
+        .into_response()
+}
+
+/// How the shadow challenger is doing against the live configuration, on the
+/// transitions that followed live `/api/suggest` calls since it was set.
+pub async fn get_predictor_challenger_comparison(
+    Query(params): Query<FetchPredictorChallengerRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let last = params.last.unwrap_or(200);
+    if last == 0 || last > MAX_METRICS_TRANSITIONS {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid last").into_response();
+    }
+    challenger_response(&state.meta, &state.outcomes, last)
+}
+
+/// Starts running a configuration in shadow. Replacing a challenger starts its
+/// comparison afresh.
+pub async fn update_predictor_challenger(
+    State(state): State<AppState>,
+    Json(payload): Json<UpdatePredictorConfigRequest>,
+) -> Response {
+    let configuration = match (payload.preset, payload.configuration) {
+        (Some(preset), None) => preset.configuration(),
+        (None, Some(configuration)) => configuration,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Bad request: Give exactly one of preset and configuration",
+            )
+                .into_response();
+        }
+    };
+    if let Err(err) = configuration.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
+    if configuration == get_predictor_configuration(&state.meta) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Bad request: Challenger is the live configuration",
+        )
+            .into_response();
+    }
+
+    let challenger = Challenger {
+        configuration,
+        since: Utc::now().timestamp_millis(),
+    };
+    if let Err(err) = set_predictor_challenger(&state.meta, Some(&challenger)) {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+    }
+    // Suggestions come in the server's timezone unless asked otherwise.
+    warm_predictor(
+        state.clone(),
+        Zone::Named(*DEFAULT_TIMEZONE),
+        challenger.configuration,
+    );
+    challenger_response(&state.meta, &state.outcomes, MAX_METRICS_TRANSITIONS)
+}
+
+/// Stops the shadow run. Its outcomes are kept but no longer compared.
+pub async fn delete_predictor_challenger(State(state): State<AppState>) -> Response {
+    if let Err(err) = set_predictor_challenger(&state.meta, None) {
+        println!("{err:?}");
+        return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+    }
+    challenger_response(&state.meta, &state.outcomes, MAX_METRICS_TRANSITIONS)
+}
+
+#[derive(Deserialize)]
+pub struct TunePredictorRequest {
+    /// Named set of configurations to try. Defaults to `stage_two`, one knob
+    /// at a time around the current configuration. The largest, `grid`, takes
+    /// about 17 seconds over `MAX_TRAINING_ENTRIES` in a release build.
+    space: Option<SearchSpace>,
+    /// Or an explicit list to try instead, fields left out taking their
+    /// default values.
+    configurations: Option<Vec<Configuration>>,
+    /// Fraction of the log that is only trained on, never scored. The rest is
+    /// split in half: the earlier half picks, the later one checks. Defaults
+    /// to 0.5.
+    warmup: Option<f64>,
+    /// How many of the best on selection to check on the later half. Defaults
+    /// to 10.
+    validate_top: Option<usize>,
+    /// Switch to the winner if it does at least as well as the current
+    /// configuration on the later half's top-3. Defaults to false.
+    adopt: Option<bool>,
+    /// IANA timezone to replay in. Defaults to the server's `TIMEZONE`.
+    tz: Option<String>,
+}
+
+const MAX_TUNING_CONFIGURATIONS: usize = 64;
+const MAX_VALIDATE_TOP: usize = 64;
+/// Each half of the scored log needs at least this many entries for the
+/// comparison to mean anything.
+const MIN_TUNING_WINDOW: usize = 50;
+
+pub async fn get_tuning_job(State(state): State<AppState>) -> Response {
+    let job = state
+        .tuning
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    (StatusCode::OK, Json(job)).into_response()
+}
+
+/// Starts replaying the log over a set of candidate configurations in the
+/// background. Poll `GET /api/predictor/tune` for progress and the report. The
+/// log is only read once the job has started, so a log that turns out too short
+/// then ends it as failed.
+pub async fn tune_predictor(
+    State(state): State<AppState>,
+    Json(payload): Json<TunePredictorRequest>,
+) -> Response {
+    let TunePredictorRequest {
+        space,
+        configurations,
+        warmup,
+        validate_top,
+        adopt,
+        tz,
+    } = payload;
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+    let warmup = warmup.unwrap_or(0.5);
+    if !(0.0..1.0).contains(&warmup) {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid warmup").into_response();
+    }
+    let validate_top = validate_top.unwrap_or(10);
+    if validate_top == 0 || validate_top > MAX_VALIDATE_TOP {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid validate_top").into_response();
+    }
+
+    let current = get_predictor_configuration(&state.meta);
+    let mut candidates = match (space, configurations) {
+        (Some(_), Some(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Bad request: space cannot be combined with configurations",
+            )
+                .into_response();
+        }
+        (space, None) => space.unwrap_or(SearchSpace::StageTwo).candidates(&current),
+        (None, Some(configurations)) => {
+            if configurations.is_empty() || configurations.len() > MAX_TUNING_CONFIGURATIONS {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "Bad request: Invalid configurations",
+                )
+                    .into_response();
+            }
+            if let Some(err) = configurations.iter().find_map(|c| c.validate().err()) {
+                return (StatusCode::BAD_REQUEST, err).into_response();
+            }
+            configurations
+                .into_iter()
+                .enumerate()
+                .map(|(i, configuration)| (format!("configuration {i}"), configuration))
+                .collect()
+        }
+    };
+    // The current configuration always competes, first so it wins ties.
+    candidates.retain(|(_, configuration)| *configuration != current);
+    candidates.insert(0, ("current".to_string(), current.clone()));
+
+    let too_short = move |length: usize| {
+        let length = length.min(MAX_TRAINING_ENTRIES);
+        (length - (length as f64 * warmup) as usize) / 2 < MIN_TUNING_WINDOW
+    };
+    // The log length is an upper bound on the valid entries, so this turns
+    // away short logs without reading them; the replay checks again.
+    if too_short(get_length(&state.meta) as usize) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Bad request: Not enough history to tune",
+        )
+            .into_response();
+    }
+
+    let started_at = Utc::now().timestamp_millis();
+    let evaluations = candidates.len() + validate_top.min(candidates.len());
+    let job = {
+        let mut job = state
+            .tuning
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if matches!(*job, TuningJob::Running { .. }) {
+            return (StatusCode::CONFLICT, "Conflict: Tuning already running").into_response();
+        }
+        *job = TuningJob::Running {
+            started_at,
+            done: 0,
+            evaluations,
+        };
+        job.clone()
+    };
+
+    let tuning = state.tuning.clone();
+    tokio::spawn(async move {
+        let progress = tuning.clone();
+        let finished = tokio::task::spawn_blocking(move || {
+            let mut entries: Vec<TrainingEntry> =
+                read_valid_entries(&state.events, &state.meta, "tune_predictor")
+                    .into_iter()
+                    .map(|(state_id, start_timestamp)| TrainingEntry {
+                        state_id,
+                        start_timestamp,
+                    })
+                    .collect();
+            entries.drain(..entries.len().saturating_sub(MAX_TRAINING_ENTRIES));
+            if too_short(entries.len()) {
+                return TuningJob::Failed {
+                    started_at,
+                    finished_at: Utc::now().timestamp_millis(),
+                    error: "Not enough history to tune".to_string(),
+                };
+            }
+            let warmup = (entries.len() as f64 * warmup) as usize;
+
+            let report = tune(
+                &entries,
+                Zone::Named(tz),
+                candidates,
+                warmup,
+                validate_top,
+                3,
+                |done| {
+                    let mut job = progress
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    *job = TuningJob::Running {
+                        started_at,
+                        done,
+                        evaluations,
+                    };
+                },
+            );
+
+            let current_held_out = match report.candidates.iter().find(|c| c.label == "current") {
+                Some(CandidateResult {
+                    held_out: Some(held_out),
+                    ..
+                }) => *held_out,
+                _ => eval_window(
+                    &entries,
+                    Zone::Named(tz),
+                    current.clone(),
+                    report.held_out_from,
+                    report.held_out_to,
+                    3,
+                )
+                .accuracy(),
+            };
+
+            let winner = &report.candidates[0];
+            let beats_current = winner
+                .held_out
+                .is_some_and(|held_out| held_out.top3 >= current_held_out.top3);
+            // Someone may have changed the configuration while this ran; theirs
+            // stands.
+            let adopted = adopt.unwrap_or(false)
+                && winner.configuration != current
+                && beats_current
+                && get_predictor_configuration(&state.meta) == current
+                && set_predictor_configuration(&state.meta, &winner.configuration)
+                    .inspect_err(|err| eprintln!("tune_predictor: {err}"))
+                    .is_ok();
+
+            TuningJob::Finished {
+                started_at,
+                finished_at: Utc::now().timestamp_millis(),
+                report,
+                current_held_out,
+                adopted,
+            }
+        })
+        .await;
+
+        let finished = finished.unwrap_or_else(|err| {
+            println!("{err:?}");
+            TuningJob::Failed {
+                started_at,
+                finished_at: Utc::now().timestamp_millis(),
+                error: err.to_string(),
+            }
+        });
+        *tuning
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = finished;
+    });
+
+    (StatusCode::ACCEPTED, Json(job)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchRecentsRequest {
+    count: Option<u64>,
+    days: Option<u32>,
+}
+
+pub async fn fetch_recent_states(
+    Query(params): Query<FetchRecentsRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let length = get_length(&state.meta);
+
+    // If the user doesn't pass in either param, we use these very large defaults.
+    let count = length.min(params.count.unwrap_or(300u64));
+    let days = params.days.unwrap_or(30u32) as i64;
+
+    // User is guarded against specifying params.count = 0 by frontend, but we
+    //   should return an empty vector rather than panic with out-of-bounds access.
+    // This also happens if length == 0.
+    if count == 0 {
+        return (StatusCode::OK, Json(Vec::<(u8, i64)>::new())).into_response();
+    }
+
+    let curr_time = Utc::now().timestamp_millis();
+    let range_start = curr_time - days * 24 * 3600 * 1000;
+
+    let mut output = Vec::<(u8, i64)>::new();
+    for i in ((length - count)..=(length - 1)).rev() {
+        let (s, t) = read_from_value(&state.events, i);
+        if !is_valid_timestamp(t) {
+            log_corrupt_entry("fetch_recent_states", i, s, t);
+        }
+        output.push((s, t));
+        if t < range_start {
+            break;
+        }
+    }
+
+    (StatusCode::OK, Json(output)).into_response()
+}
 
 const EXPORT_FORMAT_VERSION: u32 = 1;
 
@@ -752,6 +3303,9 @@ pub async fn import_data(
             }
 
             tx_meta.insert(b"len", to_ivec(new_length))?;
+            // Committed with the new history, so no predictor can train forward
+            // over it as if it had been appended.
+            bump_history_version(tx_meta)?;
 
             Ok(())
         });
@@ -777,3 +3331,462 @@ pub async fn import_data(
     )
         .into_response()
 }
+
+#[derive(Deserialize)]
+pub struct FetchYearRequest {
+    /// Calendar year to summarise. Defaults to the current one.
+    year: Option<i32>,
+    /// IANA timezone that days and months are taken in. Defaults to the
+    /// server's `TIMEZONE`.
+    tz: Option<String>,
+    /// `group` also adds the year and each month up per state group. Defaults
+    /// to `state`.
+    group_by: Option<GroupBy>,
+}
+
+#[derive(Serialize)]
+pub struct YearStateRank {
+    state: usize,
+    rank: usize,
+    total: i64,
+    /// Share of all tracked time, in percent.
+    share: f64,
+}
+
+#[derive(Serialize)]
+pub struct YearDay {
+    date: String,
+    /// Time in the productive group.
+    focus: i64,
+    tracked: i64,
+}
+
+#[derive(Serialize)]
+pub struct YearMonth {
+    label: String,
+    totals: [i64; STATE_COUNT],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_totals: Option<[i64; GROUP_COUNT]>,
+}
+
+#[derive(Serialize)]
+pub struct YearTransition {
+    from: usize,
+    to: usize,
+    count: u32,
+}
+
+#[derive(Serialize)]
+pub struct YearSession {
+    state: usize,
+    start: i64,
+    end: i64,
+    length: i64,
+}
+
+#[derive(Serialize)]
+pub struct EmergencyDay {
+    date: String,
+    total: i64,
+}
+
+#[derive(Serialize)]
+pub struct YearResponse {
+    year: i32,
+    from: i64,
+    to: i64,
+    tracked: i64,
+    totals: [i64; STATE_COUNT],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_totals: Option<[i64; GROUP_COUNT]>,
+    /// States with any time, most first.
+    ranking: Vec<YearStateRank>,
+    /// Days with the most productive time.
+    busiest_days: Vec<YearDay>,
+    /// Well-tracked days with the least productive time.
+    laziest_days: Vec<YearDay>,
+    months: Vec<YearMonth>,
+    /// The longest session of each state, longest first.
+    longest_sessions: Vec<YearSession>,
+    top_transitions: Vec<YearTransition>,
+    emergency_days: Vec<EmergencyDay>,
+}
+
+const YEAR_TOP_DAYS: usize = 5;
+const YEAR_TOP_TRANSITIONS: usize = 10;
+
+/// A calendar year at a glance: totals and rankings, busiest and laziest days,
+/// monthly trends, record sessions, common transitions and Emergency days.
+pub async fn fetch_year(
+    Query(params): Query<FetchYearRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchYearRequest { year, tz, group_by } = params;
+    let by_group = group_by == Some(GroupBy::Group);
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let Some(this_year) = local_date(&tz, now).map(|today| today.year()) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid clock").into_response();
+    };
+    let year = year.unwrap_or(this_year);
+    let offset = year.checked_sub(this_year);
+    let window = match resolve_window(&tz, None, None, Some(Period::Year), offset, now, 0) {
+        Ok(window) if offset.is_some() => window,
+        _ => return (StatusCode::BAD_REQUEST, "Bad request: Invalid year").into_response(),
+    };
+    let Some(first_day) = local_date(&tz, window.from) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid year").into_response();
+    };
+    let Some(days) = period_windows(&tz, window.from, window.to, Period::Day, 366) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid year").into_response();
+    };
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_year");
+    let tally = tally_year(&entries, &days, now);
+
+    let mut totals = [0i64; STATE_COUNT];
+    let mut months: Vec<YearMonth> = Vec::with_capacity(12);
+    let mut year_days = Vec::with_capacity(days.len());
+    let mut emergency_days = Vec::new();
+    let dates = first_day.iter_days();
+    for ((day, day_totals), date) in days.iter().zip(&tally.days).zip(dates) {
+        for (total, day_total) in totals.iter_mut().zip(day_totals) {
+            *total += day_total;
+        }
+
+        let month = date.format("%Y-%m").to_string();
+        match months.last_mut() {
+            Some(last) if last.label == month => {
+                for (total, day_total) in last.totals.iter_mut().zip(day_totals) {
+                    *total += day_total;
+                }
+            }
+            _ => months.push(YearMonth {
+                label: month,
+                totals: *day_totals,
+                group_totals: None,
+            }),
+        }
+
+        if day_totals[EMERGENCY_STATE_INDEX] > 0 {
+            emergency_days.push(EmergencyDay {
+                date: day.label.clone(),
+                total: day_totals[EMERGENCY_STATE_INDEX],
+            });
+        }
+
+        let tracked = day_totals.iter().sum::<i64>();
+        if (tracked as f64) >= (day.to - day.from) as f64 * MIN_TRACKED_FRACTION {
+            year_days.push(YearDay {
+                date: day.label.clone(),
+                focus: group_totals(day_totals)[PRODUCTIVE_GROUP_INDEX],
+                tracked,
+            });
+        }
+    }
+    let tracked = totals.iter().sum::<i64>();
+    if by_group {
+        for month in &mut months {
+            month.group_totals = Some(group_totals(&month.totals));
+        }
+    }
+
+    let mut ranking: Vec<(usize, i64)> = totals
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, total)| total > 0)
+        .collect();
+    ranking.sort_by_key(|&(_, total)| std::cmp::Reverse(total));
+    let ranking = ranking
+        .into_iter()
+        .enumerate()
+        .map(|(i, (state, total))| YearStateRank {
+            state,
+            rank: i + 1,
+            total,
+            share: total as f64 / tracked as f64 * 100.0,
+        })
+        .collect();
+
+    year_days.sort_by_key(|day| std::cmp::Reverse(day.focus));
+    let laziest_days = year_days
+        .split_off(year_days.len().saturating_sub(YEAR_TOP_DAYS))
+        .into_iter()
+        .rev()
+        .collect();
+    year_days.truncate(YEAR_TOP_DAYS);
+
+    let mut longest_sessions: Vec<YearSession> = tally
+        .longest
+        .iter()
+        .flatten()
+        .map(|session| YearSession {
+            state: session.state,
+            start: session.start,
+            end: session.end,
+            length: session.length(),
+        })
+        .collect();
+    longest_sessions.sort_by_key(|session| std::cmp::Reverse(session.length));
+
+    let mut top_transitions: Vec<YearTransition> = tally
+        .transitions
+        .iter()
+        .map(|(&(from, to), &count)| YearTransition { from, to, count })
+        .collect();
+    top_transitions.sort_by_key(|transition| std::cmp::Reverse(transition.count));
+    top_transitions.truncate(YEAR_TOP_TRANSITIONS);
+
+    (
+        StatusCode::OK,
+        Json(YearResponse {
+            year,
+            from: window.from,
+            to: window.to,
+            tracked,
+            totals,
+            group_totals: by_group.then(|| group_totals(&totals)),
+            ranking,
+            busiest_days: year_days,
+            laziest_days,
+            months,
+            longest_sessions,
+            top_transitions,
+            emergency_days,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct FetchReportRequest {
+    /// Calendar unit to review. Defaults to `week`.
+    period: Option<Period>,
+    /// Which period to review, 0 being the current one and -1 the last complete
+    /// one. Defaults to 0.
+    offset: Option<i32>,
+    /// IANA timezone that periods are aligned to. Defaults to the server's
+    /// `TIMEZONE`.
+    tz: Option<String>,
+    /// `markdown`, `html` or `json`. Defaults to `markdown`.
+    format: Option<ReportFormat>,
+    /// `group` adds time per state group. Defaults to `state`.
+    group_by: Option<GroupBy>,
+}
+
+const REPORT_TOP_SESSIONS: usize = 5;
+
+/// A review of one calendar period, ready to paste into a journal: time per
+/// state against the previous period, the longest sessions, sleep, habits and
+/// unusual days.
+pub async fn fetch_report(
+    Query(params): Query<FetchReportRequest>,
+    State(state): State<AppState>,
+) -> Response {
+    let FetchReportRequest {
+        period,
+        offset,
+        tz,
+        format,
+        group_by,
+    } = params;
+    let period = period.unwrap_or(Period::Week);
+
+    let Some(tz) = parse_timezone(tz.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid tz").into_response();
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let window = match resolve_window(&tz, None, None, Some(period), offset, now, 0) {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+    let Some(mut previous) = local_date(&tz, window.from)
+        .and_then(|start| baseline_windows(&tz, start, period, Baseline::Previous))
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Invalid offset").into_response();
+    };
+    let to_date = window.to > now;
+    if to_date {
+        let elapsed = (now - window.from).max(0);
+        for previous in &mut previous {
+            previous.to = previous.to.min(previous.from + elapsed);
+        }
+    }
+
+    let entries = read_valid_entries(&state.events, &state.meta, "fetch_report");
+    let intervals = clip_intervals(&entries, window.from, window.to, now);
+    let totals = state_totals(&intervals);
+    let previous_totals = average_totals(&entries, &previous, now);
+
+    let states = ALL_STATES_DETAILS
+        .iter()
+        .enumerate()
+        .map(|(state, detail)| StateLine {
+            state,
+            emoji: detail.emoji,
+            name: detail.name,
+            colour: detail.colour,
+            total: totals[state],
+            previous: previous_totals[state],
+        })
+        .collect();
+
+    let groups = if group_by == Some(GroupBy::Group) {
+        let current = group_totals(&totals);
+        let previous = group_totals(&previous_totals);
+        ALL_STATE_GROUPS
+            .iter()
+            .enumerate()
+            .map(|(group, detail)| GroupLine {
+                name: detail.name,
+                colour: detail.colour,
+                total: current[group],
+                previous: previous[group],
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let start_format = match period {
+        Period::Day => "%H:%M",
+        Period::Week => "%a %H:%M",
+        Period::Month | Period::Year => "%a %d %b %H:%M",
+    };
+    let mut sessions: Vec<Interval> = sessions_starting_in(&entries, window.from, window.to, now)
+        .into_iter()
+        .filter(|session| session.state != SLEEP_STATE_INDEX)
+        .collect();
+    sessions.sort_by_key(|session| std::cmp::Reverse(session.length()));
+    let top_sessions = sessions
+        .into_iter()
+        .take(REPORT_TOP_SESSIONS)
+        .map(|session| SessionLine {
+            state: session.state,
+            start: session.start,
+            start_local: tz
+                .timestamp_millis_opt(session.start)
+                .single()
+                .map(|t| t.format(start_format).to_string())
+                .unwrap_or_default(),
+            length: session.length(),
+        })
+        .collect();
+
+    let episodes = sleep_episodes(
+        &entries,
+        SLEEP_STATE_INDEX,
+        window.from,
+        window.to,
+        now,
+        i64::from(DEFAULT_MERGE_MINUTES) * 60_000,
+    );
+    let nights = group_nights(&tz, &episodes);
+    let sleep = (!nights.is_empty()).then(|| {
+        let bedtimes: Vec<u32> = nights
+            .iter()
+            .filter_map(|n| minutes_into_day(&tz, n.main.start))
+            .collect();
+        let wakes: Vec<u32> = nights
+            .iter()
+            .filter_map(|n| minutes_into_day(&tz, n.main.end))
+            .collect();
+        let sleep: Vec<Interval> = intervals
+            .iter()
+            .filter(|interval| interval.state == SLEEP_STATE_INDEX)
+            .cloned()
+            .collect();
+        SleepSummary {
+            nights: nights.len(),
+            mean_duration: nights.iter().map(|n| n.main.asleep).sum::<i64>() / nights.len() as i64,
+            mean_bedtime: mean_time_of_day(&bedtimes).map(format_minutes),
+            mean_wake: mean_time_of_day(&wakes).map(format_minutes),
+            regularity_index: sleep_regularity_index(
+                &sleep,
+                window.from,
+                window.to.min(now),
+                SRI_SAMPLE_MS,
+            ),
+        }
+    });
+
+    let Some(mut days) = period_windows(&tz, window.from, window.to, Period::Day, MAX_HABIT_DAYS)
+    else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Too many days").into_response();
+    };
+    days.retain(|day| day.from <= now);
+    let habits = read_all_habits(&state.habits)
+        .into_iter()
+        .map(|HabitEntry { habit, .. }| {
+            let days = evaluate_habit(&habit, &tz, &intervals, &days, now);
+            HabitLine {
+                name: habit.name,
+                passed: days.iter().filter(|d| d.outcome == Outcome::Pass).count(),
+                failed: days.iter().filter(|d| d.outcome == Outcome::Fail).count(),
+                current_streak: streaks(&days).current,
+            }
+        })
+        .collect();
+
+    let anomalies = match find_anomalies(
+        &tz,
+        &entries,
+        &window,
+        DEFAULT_HISTORY_WEEKS,
+        DEFAULT_ANOMALY_THRESHOLD,
+        false,
+        now,
+    ) {
+        Ok((_, _, days)) => days
+            .into_iter()
+            .map(|day| AnomalyLine {
+                date: day.date,
+                deviations: day.deviations,
+            })
+            .collect(),
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let report = Report {
+        period,
+        label: window.label,
+        from: window.from,
+        to: window.to,
+        tz: tz.name().to_string(),
+        previous_label: previous
+            .first()
+            .map(|w| w.label.clone())
+            .unwrap_or_default(),
+        to_date,
+        tracked: totals.iter().sum(),
+        previous_tracked: previous_totals.iter().sum(),
+        states,
+        groups,
+        top_sessions,
+        sleep,
+        habits,
+        anomalies,
+    };
+
+    match format.unwrap_or(ReportFormat::Markdown) {
+        ReportFormat::Json => (StatusCode::OK, Json(report)).into_response(),
+        ReportFormat::Markdown => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            render_markdown(&report),
+        )
+            .into_response(),
+        ReportFormat::Html => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            render_html(&report),
+        )
+            .into_response(),
+    }
+}
@@ -17,38 +17,92 @@ use axum::{
     Router,
     extract::DefaultBodyLimit,
     middleware,
-    routing::{get, post, put},
+    routing::{delete, get, post, put},
+};
+use sled::Tree;
+use std::{
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
 };
-use std::{env, net::SocketAddr};
 use tokio::net::TcpListener;
 use tower_http::compression::CompressionLayer;
 
+mod accuracy;
+
+mod analytics;
+
 mod auth;
 use auth::auth_user;
 
 mod constants;
-use constants::AppState;
+use constants::DEFAULT_TIMEZONE;
+
+mod habits;
 
 mod handlers;
 use handlers::{
-    add_entry, export_data, fetch_length, fetch_recent_states, fetch_states, fetch_summary_data,
-    force_set_length, get_entry, import_data, not_found, suggest_next_states, update_entry,
+    add_entry, create_habit, delete_habit, delete_predictor_challenger, export_data,
+    fetch_anomalies, fetch_comparison, fetch_durations, fetch_focus, fetch_forecast,
+    fetch_habit_status, fetch_heatmap, fetch_length, fetch_predictor_metrics, fetch_recent_states,
+    fetch_report, fetch_series, fetch_sleep, fetch_states, fetch_summary_data, fetch_transitions,
+    fetch_year, force_set_length, get_entry, get_habit, get_predictor_challenger_comparison,
+    get_predictor_config, get_tuning_job, import_data, list_habits, not_found, suggest_duration,
+    suggest_next_states, tune_predictor, update_entry, update_habit, update_predictor_challenger,
+    update_predictor_config,
 };
 
 mod predictor;
+mod predictor_cache;
+use predictor_cache::PredictorCache;
+mod report;
+
+mod timezone;
+
+mod tls;
+
+mod tuning;
+use tls::{TlsSettings, serve_tls};
+use tuning::TuningJob;
 
 mod utils;
 
+// Lives here rather than in `constants` so the evaluation example, which pulls
+// in `constants.rs` on its own, doesn't need the predictor cache.
+#[derive(Clone)]
+pub struct AppState {
+    pub events: Tree,
+    pub meta: Tree,
+    pub habits: Tree,
+    /// How each transition compared with the suggestions made before it.
+    pub outcomes: Tree,
+    pub predictors: Arc<PredictorCache>,
+    pub tuning: Arc<Mutex<TuningJob>>,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Allow .env to not exist and environment variables to be passed directly, for example in Docker
     dotenvy::dotenv().ok();
 
+    // Fail on an unknown TIMEZONE now rather than on the first request.
+    println!("Default timezone: {}", *DEFAULT_TIMEZONE);
+
     let db = sled::open(env::var("DB_PATH").unwrap())?;
     let events = db.open_tree("events")?;
     let meta = db.open_tree("meta")?;
+    let habits = db.open_tree("habits")?;
+    let outcomes = db.open_tree("outcomes")?;
+    let predictors = PredictorCache::new(db.open_tree("predictors")?);
 
-    let app_state = AppState { events, meta };
+    let app_state = AppState {
+        events,
+        meta,
+        habits,
+        outcomes,
+        predictors: Arc::new(predictors),
+        tuning: Arc::new(Mutex::new(TuningJob::Idle)),
+    };
 
     let protected_app = Router::new()
         .route("/api/states", get(fetch_states))
@@ -56,10 +110,45 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/entry/{entry_idx}", get(get_entry))
         .route("/api/entry/{entry_idx}", put(update_entry))
         .route("/api/data", get(fetch_summary_data))
+        .route("/api/series", get(fetch_series))
+        .route("/api/heatmap", get(fetch_heatmap))
+        .route("/api/transitions", get(fetch_transitions))
+        .route("/api/durations", get(fetch_durations))
+        .route("/api/focus", get(fetch_focus))
+        .route("/api/sleep", get(fetch_sleep))
+        .route("/api/compare", get(fetch_comparison))
+        .route("/api/anomalies", get(fetch_anomalies))
+        .route("/api/report", get(fetch_report))
+        .route("/api/year", get(fetch_year))
+        .route("/api/habits", get(list_habits))
+        .route("/api/habits", post(create_habit))
+        .route("/api/habits/status", get(fetch_habit_status))
+        .route("/api/habits/{habit_id}", get(get_habit))
+        .route("/api/habits/{habit_id}", put(update_habit))
+        .route("/api/habits/{habit_id}", delete(delete_habit))
         .route("/api/length", get(fetch_length))
         .route("/api/length", post(force_set_length))
         .route("/api/recents", get(fetch_recent_states))
         .route("/api/suggest", get(suggest_next_states))
+        .route("/api/suggest/duration", get(suggest_duration))
+        .route("/api/forecast", get(fetch_forecast))
+        .route("/api/predictor/config", get(get_predictor_config))
+        .route("/api/predictor/config", put(update_predictor_config))
+        .route("/api/predictor/metrics", get(fetch_predictor_metrics))
+        .route(
+            "/api/predictor/challenger",
+            get(get_predictor_challenger_comparison),
+        )
+        .route(
+            "/api/predictor/challenger",
+            put(update_predictor_challenger),
+        )
+        .route(
+            "/api/predictor/challenger",
+            delete(delete_predictor_challenger),
+        )
+        .route("/api/predictor/tune", get(get_tuning_job))
+        .route("/api/predictor/tune", post(tune_predictor))
         .route("/api/export", get(export_data))
         .route(
             "/api/import",
@@ -76,11 +165,35 @@ async fn main() -> anyhow::Result<()> {
         .unwrap()
         .parse::<SocketAddr>()
         .expect("Wrong address format.");
-    let listener = TcpListener::bind(addr).await.unwrap();
 
-    println!("Server running on {addr}");
+    // TLS is opt-in: both paths must be set, otherwise serve plain HTTP as before
+    // and leave TLS to a reverse proxy.
+    match (
+        env::var("TLS_CERT_PATH").ok(),
+        env::var("TLS_KEY_PATH").ok(),
+    ) {
+        (Some(cert_path), Some(key_path)) => {
+            let redirect_addr = env::var("HTTP_REDIRECT_ADDR").ok().map(|value| {
+                value
+                    .parse::<SocketAddr>()
+                    .expect("Wrong redirect address format.")
+            });
+            let settings = TlsSettings {
+                cert_path,
+                key_path,
+                redirect_addr,
+            };
+            serve_tls(app, addr, settings).await?;
+        }
+        (None, None) => {
+            let listener = TcpListener::bind(addr).await.unwrap();
+
+            println!("Server running on {addr}");
 
-    axum::serve(listener, app).await.unwrap();
+            axum::serve(listener, app).await.unwrap();
+        }
+        _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together."),
+    }
 
     Ok(())
 }
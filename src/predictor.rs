@@ -33,11 +33,12 @@
 //! `docs/predictor-tuning.md` for the measurements behind each of those.
 
 use chrono::{DateTime, Datelike, FixedOffset, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
 
 use crate::constants::STATE_COUNT;
 
 /// How the candidate sets of the matching tables are turned into a ranked list.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum Ranking {
     /// Take the trusted table's candidates first, then the alternate's, then the
@@ -46,10 +47,14 @@ pub enum Ranking {
     /// Score every state by a weighted sum of the confidence counters of every
     /// matching table, then rank by score.
     Fusion,
+    /// Blend each state's share of the fused score with the
+    /// [`MarkovHourRanker`] estimate, weighted by
+    /// [`Configuration::ensemble_markov_weight`].
+    Ensemble,
 }
 
 /// How much resolution the weekday half of the time context keeps.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub enum WeekdayMode {
     /// One bucket per weekday, 1 = Sunday.
@@ -60,8 +65,23 @@ pub enum WeekdayMode {
     Ignored,
 }
 
-/// Tunables for the predictor.
-#[derive(Clone, Debug)]
+/// Bounds [`Configuration::validate`] holds configurations to, so one sent over
+/// the API can neither panic nor allocate without limit.
+pub const MAX_TRAINING_ENTRIES: usize = 100_000;
+const MAX_TABLE_SIZE: usize = 1 << 16;
+const MAX_TAGGED_TABLES: usize = 16;
+const MAX_HISTORY_LENGTH: usize = 256;
+const MAX_AGING_INTERVAL: u64 = 1 << 32;
+const MAX_FUSION_LENGTH_BASE: f64 = 16.0;
+
+/// [`Configuration::cold_start_entries`] by default: the longest cold start
+/// that gained top-1 without costing top-3 in `docs/predictor-tuning.md`.
+const COLD_START_ENTRIES: usize = 25;
+
+/// Tunables for the predictor. Fields missing when deserialising take their
+/// [`Default`] values.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Configuration {
     /// Geometrically increasing history lengths, one tagged table per entry.
     pub history_lengths: Vec<usize>,
@@ -95,11 +115,17 @@ pub struct Configuration {
     pub fusion_prior_weight: f64,
     /// Per-step multiplier applied to the global recency scores.
     pub recency_decay: f64,
+    /// Under [`Ranking::Ensemble`], how much the Markov-hour estimate counts
+    /// against the fused score, from 0 to 1.
+    pub ensemble_markov_weight: f64,
+    /// Until this many entries have been trained on, rank as
+    /// [`Ranking::Ensemble`] whatever `ranking` says. The tagged tables only
+    /// fill in with history, and until they do a bigram count does better.
+    pub cold_start_entries: usize,
 }
 
 impl Configuration {
     /// The iOS client's configuration, reproduced exactly.
-    #[allow(dead_code)]
     pub fn legacy() -> Self {
         Self {
             history_lengths: vec![1, 2, 4, 8, 16, 32],
@@ -119,6 +145,89 @@ impl Configuration {
             fusion_length_base: 2.0,
             fusion_prior_weight: 0.0,
             recency_decay: 0.95,
+            ensemble_markov_weight: 0.5,
+            cold_start_entries: 0,
+        }
+    }
+
+    /// Checks everything [`ActivityPredictor::new`] relies on, plus limits on
+    /// table sizes and history lengths.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.history_lengths.len() > MAX_TAGGED_TABLES
+            || self
+                .history_lengths
+                .iter()
+                .any(|&length| length == 0 || length > MAX_HISTORY_LENGTH)
+        {
+            return Err("Bad request: Invalid history_lengths");
+        }
+        for size in [self.base_table_size, self.tagged_table_size] {
+            if size == 0 || size > MAX_TABLE_SIZE {
+                return Err("Bad request: Invalid table size");
+            }
+        }
+        if self.usefulness_aging_interval == 0
+            || self.usefulness_aging_interval > MAX_AGING_INTERVAL
+        {
+            return Err("Bad request: Invalid usefulness_aging_interval");
+        }
+        if self.maximum_training_entries == 0
+            || self.maximum_training_entries > MAX_TRAINING_ENTRIES
+        {
+            return Err("Bad request: Invalid maximum_training_entries");
+        }
+        if !(1..=24).contains(&self.hour_bucket) {
+            return Err("Bad request: Invalid hour_bucket");
+        }
+        if self.candidate_capacity == 0 || self.candidate_capacity > STATE_COUNT {
+            return Err("Bad request: Invalid candidate_capacity");
+        }
+        if self.counter_max == 0 {
+            return Err("Bad request: Invalid counter_max");
+        }
+        if !(self.fusion_length_base > 0.0 && self.fusion_length_base <= MAX_FUSION_LENGTH_BASE) {
+            return Err("Bad request: Invalid fusion_length_base");
+        }
+        if !(self.fusion_prior_weight >= 0.0 && self.fusion_prior_weight.is_finite()) {
+            return Err("Bad request: Invalid fusion_prior_weight");
+        }
+        if !(0.0..=1.0).contains(&self.recency_decay) {
+            return Err("Bad request: Invalid recency_decay");
+        }
+        if !(0.0..=1.0).contains(&self.ensemble_markov_weight) {
+            return Err("Bad request: Invalid ensemble_markov_weight");
+        }
+        if self.cold_start_entries > MAX_TRAINING_ENTRIES {
+            return Err("Bad request: Invalid cold_start_entries");
+        }
+        Ok(())
+    }
+
+    /// The preset this configuration is, if it is one exactly.
+    pub fn preset(&self) -> Option<Preset> {
+        Preset::ALL
+            .into_iter()
+            .find(|preset| preset.configuration() == *self)
+    }
+}
+
+/// Named configurations that can be switched to wholesale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// [`Configuration::default`], tuned for top-3 accuracy.
+    Default,
+    /// [`Configuration::legacy`], matching the iOS client.
+    Legacy,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 2] = [Preset::Default, Preset::Legacy];
+
+    pub fn configuration(self) -> Configuration {
+        match self {
+            Preset::Default => Configuration::default(),
+            Preset::Legacy => Configuration::legacy(),
         }
     }
 }
@@ -143,6 +252,8 @@ impl Default for Configuration {
             fusion_length_base: 2.0,
             fusion_prior_weight: 0.4,
             recency_decay: 0.95,
+            ensemble_markov_weight: 0.5,
+            cold_start_entries: COLD_START_ENTRIES,
         }
     }
 }
@@ -157,13 +268,15 @@ pub struct TrainingEntry {
 struct Context {
     weekday: u32,
     hour: u32,
+    /// Unbucketed, for [`MarkovHourRanker`].
+    local_hour: u32,
     elapsed: u32,
     current_state_id: Option<usize>,
     is_tracking: bool,
     history: Vec<usize>,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 struct Candidate {
     state_id: usize,
     counter: u8,
@@ -173,7 +286,7 @@ struct Candidate {
 /// A tiny set of competing next-activity candidates, each with a saturating
 /// confidence counter. Both the size of the set and the counter ceiling come from
 /// [`Configuration`]; the iOS client uses three candidates and 3-bit counters.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct CandidateSet {
     values: Vec<Candidate>,
 }
@@ -235,21 +348,457 @@ impl CandidateSet {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct TaggedEntry {
     tag: u16,
     candidates: CandidateSet,
     usefulness: u8,
 }
 
+/// A suggested next activity and how likely it is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prediction {
+    pub state_id: usize,
+    /// Between 0 and 1; see [`ActivityPredictor::scored_predictions`].
+    pub probability: f64,
+    /// The raw fused score, which the explanations sum to. Under the ensemble
+    /// the probability comes from the blend instead, so the two can disagree.
+    pub score: f64,
+}
+
+/// Why a state scored what it did under fusion: the base table, every tagged
+/// table that matched the context, and the recency prior, each with what it
+/// added to the state's fused score. The contributions sum to that score.
+#[derive(Clone, Debug, Serialize)]
+pub struct Explanation {
+    pub base: TableEvidence,
+    pub tables: Vec<TableEvidence>,
+    /// What the global recency prior added.
+    pub prior: f64,
+}
+
+/// One table's entry for the context being predicted in.
+#[derive(Clone, Debug, Serialize)]
+pub struct TableEvidence {
+    /// Which tagged table, or `None` for the base table.
+    pub table: Option<usize>,
+    /// How many past activities the entry is keyed on; 0 for the base table.
+    pub history_length: usize,
+    /// Hour-of-day bucket in the key, if this table uses the time context.
+    pub hour_bucket: Option<u32>,
+    /// Elapsed-time bucket in the key, if this table uses it.
+    pub elapsed_bucket: Option<u32>,
+    pub usefulness: u8,
+    pub weight: f64,
+    /// Every state the entry tracks, most confident first.
+    pub candidates: Vec<CandidateCount>,
+    pub contribution: f64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct CandidateCount {
+    pub state_id: usize,
+    pub counter: u8,
+}
+
+/// How many recent durations each duration context keeps. Older ones are
+/// dropped so estimates follow a changing routine.
+const DURATION_SAMPLES: usize = 64;
+/// A duration context needs this many samples (that outlast the time already
+/// elapsed) before it is preferred over a less specific one.
+const MIN_DURATION_SAMPLES: usize = 5;
+
+/// The most specific context a [`DurationEstimate`] could be drawn from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DurationContext {
+    /// Same state, hour bucket and preceding state.
+    History,
+    /// Same state and hour bucket.
+    Hour,
+    /// Same state at any time.
+    State,
+}
+
+/// Quantiles of how long an activity lasts in total, in milliseconds, among
+/// past runs that lasted at least as long as it already has.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct DurationEstimate {
+    pub median: i64,
+    pub p25: i64,
+    pub p75: i64,
+    pub samples: usize,
+    pub context: DurationContext,
+}
+
+/// The entry the log currently ends on, which has no duration yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenEntry {
+    pub state_id: usize,
+    pub started_at: i64,
+    pub previous_state_id: Option<usize>,
+    hour: u32,
+}
+
+/// Recent durations of each state, backed off from (state, hour bucket,
+/// preceding state) to (state, hour bucket) to the state alone, the same way
+/// the tagged tables fall back to shorter histories.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DurationModel {
+    hour_buckets: usize,
+    by_state: Vec<Vec<i64>>,
+    by_hour: Vec<Vec<i64>>,
+    by_history: Vec<Vec<i64>>,
+    open: Option<OpenEntry>,
+}
+
+impl DurationModel {
+    fn new(hour_bucket_width: u32) -> Self {
+        let hour_buckets = hour_bucket(23, hour_bucket_width) as usize + 1;
+        Self {
+            hour_buckets,
+            by_state: vec![Vec::new(); STATE_COUNT],
+            by_hour: vec![Vec::new(); STATE_COUNT * hour_buckets],
+            by_history: vec![Vec::new(); STATE_COUNT * hour_buckets * (STATE_COUNT + 1)],
+            open: None,
+        }
+    }
+
+    fn fits(&self, hour_bucket_width: u32) -> bool {
+        let hour_buckets = hour_bucket(23, hour_bucket_width) as usize + 1;
+        self.hour_buckets == hour_buckets
+            && self.by_state.len() == STATE_COUNT
+            && self.by_hour.len() == STATE_COUNT * hour_buckets
+            && self.by_history.len() == STATE_COUNT * hour_buckets * (STATE_COUNT + 1)
+            && self.open.is_none_or(|open| {
+                open.state_id < STATE_COUNT
+                    && (open.hour as usize) < hour_buckets
+                    && open.previous_state_id.is_none_or(|s| s < STATE_COUNT)
+            })
+    }
+
+    /// Notes that `state_id` started at `at`, in hour bucket `hour`, which
+    /// closes the open entry and records how long it lasted.
+    fn start(&mut self, state_id: usize, at: i64, hour: u32) {
+        if let Some(open) = self.open {
+            let duration = at - open.started_at;
+            if duration > 0 {
+                let (state, hour, history) =
+                    self.indices(open.state_id, open.hour, open.previous_state_id);
+                for samples in [
+                    &mut self.by_state[state],
+                    &mut self.by_hour[hour],
+                    &mut self.by_history[history],
+                ] {
+                    if samples.len() == DURATION_SAMPLES {
+                        samples.remove(0);
+                    }
+                    samples.push(duration);
+                }
+            }
+        }
+        self.move_to(state_id, at, hour);
+    }
+
+    /// Like [`start`](Self::start), but without recording anything about the
+    /// entry it closes.
+    fn move_to(&mut self, state_id: usize, at: i64, hour: u32) {
+        self.open = Some(OpenEntry {
+            state_id,
+            started_at: at,
+            previous_state_id: self.open.map(|open| open.state_id),
+            hour,
+        });
+    }
+
+    fn estimate(
+        &self,
+        state_id: usize,
+        hour: u32,
+        previous_state_id: Option<usize>,
+        elapsed: i64,
+    ) -> Option<DurationEstimate> {
+        let (state, hour, history) = self.indices(state_id, hour, previous_state_id);
+        [
+            (
+                &self.by_history[history],
+                DurationContext::History,
+                MIN_DURATION_SAMPLES,
+            ),
+            (
+                &self.by_hour[hour],
+                DurationContext::Hour,
+                MIN_DURATION_SAMPLES,
+            ),
+            (&self.by_state[state], DurationContext::State, 1),
+        ]
+        .into_iter()
+        .find_map(|(samples, context, minimum)| {
+            let mut outlasting: Vec<i64> =
+                samples.iter().copied().filter(|&d| d > elapsed).collect();
+            if outlasting.len() < minimum {
+                return None;
+            }
+            outlasting.sort_unstable();
+            let quantile =
+                |q: f64| outlasting[((outlasting.len() - 1) as f64 * q).round() as usize];
+            Some(DurationEstimate {
+                median: quantile(0.5),
+                p25: quantile(0.25),
+                p75: quantile(0.75),
+                samples: outlasting.len(),
+                context,
+            })
+        })
+    }
+
+    fn indices(
+        &self,
+        state_id: usize,
+        hour: u32,
+        previous_state_id: Option<usize>,
+    ) -> (usize, usize, usize) {
+        let hour = state_id * self.hour_buckets + (hour as usize).min(self.hour_buckets - 1);
+        let previous = previous_state_id.map_or(0, |s| s + 1);
+        (state_id, hour, hour * (STATE_COUNT + 1) + previous)
+    }
+}
+
+/// One stretch of a forecast schedule, `[start, end)` in epoch milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct ForecastBlock {
+    pub state_id: usize,
+    pub start: i64,
+    pub end: i64,
+    /// How likely this state was to follow the block before it. `None` for
+    /// the entry that is already running.
+    pub probability: Option<f64>,
+}
+
+/// A ranking of next activities learnt from transitions alone, without the
+/// tagged tables. These are the baselines `examples/eval_predictor.rs` measures
+/// the predictor against; [`MarkovHourRanker`] also backs
+/// [`Ranking::Ensemble`].
+pub trait Ranker {
+    /// Learns that `state_id` followed `previous_state_id`, starting in local
+    /// hour `hour`.
+    fn observe(&mut self, previous_state_id: Option<usize>, state_id: usize, hour: u32);
+
+    /// What each state is ranked by, higher first. Only comparable within one
+    /// call.
+    fn scores(&self, current_state_id: Option<usize>, hour: u32) -> [f64; STATE_COUNT];
+
+    /// Up to `limit` states, best first, never `current_state_id`. Ties go to
+    /// the lower index.
+    #[allow(dead_code)]
+    fn rank(&self, current_state_id: Option<usize>, hour: u32, limit: usize) -> Vec<usize> {
+        let scores = self.scores(current_state_id, hour);
+        let mut ranked: Vec<usize> = (0..STATE_COUNT)
+            .filter(|&s| Some(s) != current_state_id)
+            .collect();
+        ranked.sort_by(|&a, &b| {
+            scores[b]
+                .partial_cmp(&scores[a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.cmp(&b))
+        });
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+/// Most often logged overall.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FrequencyRanker {
+    counts: [u64; STATE_COUNT],
+}
+
+impl Ranker for FrequencyRanker {
+    fn observe(&mut self, _previous_state_id: Option<usize>, state_id: usize, _hour: u32) {
+        self.counts[state_id] += 1;
+    }
+
+    fn scores(&self, _current_state_id: Option<usize>, _hour: u32) -> [f64; STATE_COUNT] {
+        self.counts.map(|count| count as f64)
+    }
+}
+
+/// Most recently logged.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub struct RecencyRanker {
+    last_seen: [u64; STATE_COUNT],
+    sequence: u64,
+}
+
+impl Ranker for RecencyRanker {
+    fn observe(&mut self, _previous_state_id: Option<usize>, state_id: usize, _hour: u32) {
+        self.sequence += 1;
+        self.last_seen[state_id] = self.sequence;
+    }
+
+    fn scores(&self, _current_state_id: Option<usize>, _hour: u32) -> [f64; STATE_COUNT] {
+        self.last_seen.map(|sequence| sequence as f64)
+    }
+}
+
+/// Most often logged after the current state, ties going to whatever is most
+/// often logged after anything. With no current state, [`FrequencyRanker`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarkovRanker {
+    frequency: FrequencyRanker,
+    /// How often each state followed any other.
+    targets: [u64; STATE_COUNT],
+    /// Row `s` counts what followed `s`.
+    transitions: Vec<[u64; STATE_COUNT]>,
+}
+
+impl Default for MarkovRanker {
+    fn default() -> Self {
+        Self {
+            frequency: FrequencyRanker::default(),
+            targets: [0; STATE_COUNT],
+            transitions: vec![[0; STATE_COUNT]; STATE_COUNT],
+        }
+    }
+}
+
+impl MarkovRanker {
+    fn fits(&self) -> bool {
+        self.transitions.len() == STATE_COUNT
+    }
+}
+
+impl Ranker for MarkovRanker {
+    fn observe(&mut self, previous_state_id: Option<usize>, state_id: usize, hour: u32) {
+        self.frequency.observe(previous_state_id, state_id, hour);
+        if let Some(previous) = previous_state_id {
+            self.transitions[previous][state_id] += 1;
+            self.targets[state_id] += 1;
+        }
+    }
+
+    fn scores(&self, current_state_id: Option<usize>, hour: u32) -> [f64; STATE_COUNT] {
+        let Some(current) = current_state_id else {
+            return self.frequency.scores(None, hour);
+        };
+        lexicographic(&self.transitions[current], &self.targets)
+    }
+}
+
+/// [`MarkovRanker`] within the same block of hours: most often logged after
+/// the current state at this time of day, ties going to most often after it at
+/// any time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarkovHourRanker {
+    hour_bucket: u32,
+    markov: MarkovRanker,
+    /// Row `s * buckets + h` counts what followed `s` in hour bucket `h`.
+    by_hour: Vec<[u64; STATE_COUNT]>,
+}
+
+impl MarkovHourRanker {
+    /// Buckets hours `hour_bucket` at a time, like
+    /// [`Configuration::hour_bucket`].
+    pub fn new(hour_bucket: u32) -> Self {
+        Self {
+            hour_bucket,
+            markov: MarkovRanker::default(),
+            by_hour: vec![[0; STATE_COUNT]; STATE_COUNT * Self::buckets(hour_bucket)],
+        }
+    }
+
+    fn buckets(hour_bucket: u32) -> usize {
+        self::hour_bucket(23, hour_bucket) as usize + 1
+    }
+
+    fn fits(&self, hour_bucket: u32) -> bool {
+        self.hour_bucket == hour_bucket
+            && self.markov.fits()
+            && self.by_hour.len() == STATE_COUNT * Self::buckets(hour_bucket)
+    }
+
+    fn row(&self, state_id: usize, hour: u32) -> usize {
+        let buckets = Self::buckets(self.hour_bucket);
+        state_id * buckets + (self::hour_bucket(hour, self.hour_bucket) as usize).min(buckets - 1)
+    }
+
+    /// How likely each state is to come next, as probabilities: the counts
+    /// for this state and hour, smoothed towards those for this state at any
+    /// hour, smoothed in turn towards overall frequency.
+    pub fn estimate(&self, current_state_id: Option<usize>, hour: u32) -> [f64; STATE_COUNT] {
+        let counts = &self.markov.frequency.counts;
+        let total: u64 = counts.iter().sum();
+        let frequency: [f64; STATE_COUNT] =
+            std::array::from_fn(|s| (counts[s] + 1) as f64 / (total + STATE_COUNT as u64) as f64);
+        let Some(current) = current_state_id else {
+            return frequency;
+        };
+        let smooth = |row: &[u64; STATE_COUNT], prior: [f64; STATE_COUNT]| {
+            let total = row.iter().sum::<u64>() as f64;
+            std::array::from_fn(|s| (row[s] as f64 + prior[s]) / (total + 1.0))
+        };
+        let markov = smooth(&self.markov.transitions[current], frequency);
+        smooth(&self.by_hour[self.row(current, hour)], markov)
+    }
+}
+
+impl Ranker for MarkovHourRanker {
+    fn observe(&mut self, previous_state_id: Option<usize>, state_id: usize, hour: u32) {
+        self.markov.observe(previous_state_id, state_id, hour);
+        if let Some(previous) = previous_state_id {
+            let row = self.row(previous, hour);
+            self.by_hour[row][state_id] += 1;
+        }
+    }
+
+    fn scores(&self, current_state_id: Option<usize>, hour: u32) -> [f64; STATE_COUNT] {
+        let Some(current) = current_state_id else {
+            return self.markov.scores(None, hour);
+        };
+        lexicographic(
+            &self.by_hour[self.row(current, hour)],
+            &self.markov.transitions[current],
+        )
+    }
+}
+
+/// Scores that order by `first`, then by `second`, exactly.
+fn lexicographic(first: &[u64; STATE_COUNT], second: &[u64; STATE_COUNT]) -> [f64; STATE_COUNT] {
+    let scale = second.iter().max().map_or(1, |&max| max + 1) as f64;
+    std::array::from_fn(|s| first[s] as f64 * scale + second[s] as f64)
+}
+
 struct Match {
     table: usize,
     index: usize,
 }
 
-pub struct ActivityPredictor {
+/// Everything a predictor has learnt, without its timezone or configuration, so
+/// it can be stored and picked up again instead of retrained.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PredictorSnapshot {
+    base_table: Vec<CandidateSet>,
+    tagged_tables: Vec<Vec<Option<TaggedEntry>>>,
+    global_counts: [u64; STATE_COUNT],
+    recency_scores: [f64; STATE_COUNT],
+    global_last_seen: [i64; STATE_COUNT],
+    recent_history: Vec<usize>,
+    training_sequence: i64,
+    last_event_at: Option<i64>,
+    /// Absent in snapshots from before durations were tracked, which forces a
+    /// retrain.
+    #[serde(default)]
+    durations: Option<DurationModel>,
+    /// Likewise for snapshots from before the Markov-hour estimate.
+    #[serde(default)]
+    markov: Option<MarkovHourRanker>,
+}
+
+#[derive(Clone)]
+pub struct ActivityPredictor<Z: TimeZone = FixedOffset> {
     configuration: Configuration,
-    offset: FixedOffset,
+    timezone: Z,
     base_table: Vec<CandidateSet>,
     tagged_tables: Vec<Vec<Option<TaggedEntry>>>,
     global_counts: [u64; STATE_COUNT],
@@ -258,17 +807,16 @@ pub struct ActivityPredictor {
     recent_history: Vec<usize>,
     training_sequence: i64,
     last_event_at: Option<i64>,
+    durations: DurationModel,
+    markov: MarkovHourRanker,
 }
 
-impl ActivityPredictor {
-    /// Trains a predictor on `entries`. Weekday/hour context is derived in the
-    /// `offset` timezone, so the caller must pass the user's local UTC offset for
-    /// time-of-day patterns to line up.
-    pub fn new(
-        entries: &[TrainingEntry],
-        offset: FixedOffset,
-        configuration: Configuration,
-    ) -> Self {
+impl<Z: TimeZone> ActivityPredictor<Z> {
+    /// Trains a predictor on `entries`. Weekday/hour context is derived in
+    /// `timezone`, so the caller must pass the user's local zone for time-of-day
+    /// patterns to line up. A named zone keeps them lined up across DST changes;
+    /// a fixed offset shifts them by an hour for half the year.
+    pub fn new(entries: &[TrainingEntry], timezone: Z, configuration: Configuration) -> Self {
         assert!(configuration.base_table_size > 0);
         assert!(configuration.tagged_table_size > 0);
         assert!(configuration.usefulness_aging_interval > 0);
@@ -280,9 +828,11 @@ impl ActivityPredictor {
             .map(|_| vec![None; configuration.tagged_table_size])
             .collect();
 
+        let durations = DurationModel::new(configuration.hour_bucket);
+        let markov = MarkovHourRanker::new(configuration.hour_bucket);
         let mut predictor = Self {
             configuration,
-            offset,
+            timezone,
             base_table,
             tagged_tables,
             global_counts: [0; STATE_COUNT],
@@ -291,6 +841,8 @@ impl ActivityPredictor {
             recent_history: Vec::new(),
             training_sequence: 0,
             last_event_at: None,
+            durations,
+            markov,
         };
 
         let mut ordered = entries.to_vec();
@@ -306,6 +858,67 @@ impl ActivityPredictor {
         predictor
     }
 
+    pub fn snapshot(&self) -> PredictorSnapshot {
+        PredictorSnapshot {
+            base_table: self.base_table.clone(),
+            tagged_tables: self.tagged_tables.clone(),
+            global_counts: self.global_counts,
+            recency_scores: self.recency_scores,
+            global_last_seen: self.global_last_seen,
+            recent_history: self.recent_history.clone(),
+            training_sequence: self.training_sequence,
+            last_event_at: self.last_event_at,
+            durations: Some(self.durations.clone()),
+            markov: Some(self.markov.clone()),
+        }
+    }
+
+    /// Picks a predictor back up from `snapshot`. `None` if the snapshot doesn't
+    /// fit `configuration` (its tables are sized differently) or names a state
+    /// that no longer exists, in which case the caller should retrain.
+    pub fn restore(
+        snapshot: PredictorSnapshot,
+        timezone: Z,
+        configuration: Configuration,
+    ) -> Option<Self> {
+        let valid_set = |set: &CandidateSet| {
+            set.values.len() <= configuration.candidate_capacity
+                && set.values.iter().all(|c| c.state_id < STATE_COUNT)
+        };
+        let fits = snapshot.base_table.len() == configuration.base_table_size
+            && snapshot.base_table.iter().all(valid_set)
+            && snapshot.tagged_tables.len() == configuration.history_lengths.len()
+            && snapshot.tagged_tables.iter().all(|table| {
+                table.len() == configuration.tagged_table_size
+                    && table.iter().flatten().all(|e| valid_set(&e.candidates))
+            })
+            && snapshot.recent_history.iter().all(|&s| s < STATE_COUNT);
+        if !fits {
+            return None;
+        }
+        let durations = snapshot
+            .durations
+            .filter(|durations| durations.fits(configuration.hour_bucket))?;
+        let markov = snapshot
+            .markov
+            .filter(|markov| markov.fits(configuration.hour_bucket))?;
+
+        Some(Self {
+            configuration,
+            timezone,
+            base_table: snapshot.base_table,
+            tagged_tables: snapshot.tagged_tables,
+            global_counts: snapshot.global_counts,
+            recency_scores: snapshot.recency_scores,
+            global_last_seen: snapshot.global_last_seen,
+            recent_history: snapshot.recent_history,
+            training_sequence: snapshot.training_sequence,
+            last_event_at: snapshot.last_event_at,
+            durations,
+            markov,
+        })
+    }
+
     /// Returns up to `limit` suggested next activities, best first. Never suggests
     /// `current_state_id`, never repeats, and always fills up to `limit` (falling
     /// back to globally recent/frequent activities, then canonical order).
@@ -321,10 +934,125 @@ impl ActivityPredictor {
 
         let context = self.make_context(at, current_state_id, self.recent_history.clone());
         let matches = self.matching_entries(&context);
+        self.ranking(&context, &matches, limit)
+    }
+
+    /// Like [`predictions`](Self::predictions), but with how likely each
+    /// suggestion is. Probabilities are each state's share of the fused score
+    /// over every state but the current one, or of the blended score when
+    /// ranking as [`Ranking::Ensemble`], so across all candidates they sum to 1.
+    /// With no evidence at all every candidate is equally likely.
+    pub fn scored_predictions(
+        &self,
+        at: i64,
+        current_state_id: Option<usize>,
+        limit: usize,
+    ) -> Vec<Prediction> {
+        if limit == 0 {
+            return Vec::new();
+        }
+
+        let context = self.make_context(at, current_state_id, self.recent_history.clone());
+        let matches = self.matching_entries(&context);
+        let (scores, _) = self.fused_scores(&context, &matches);
+        let probabilities = if self.is_ensemble() {
+            self.ensemble_scores(&context, &matches)
+        } else {
+            shares(&scores, current_state_id)
+        };
 
-        if self.configuration.ranking == Ranking::Fusion {
+        self.ranking(&context, &matches, limit)
+            .into_iter()
+            .map(|state_id| Prediction {
+                state_id,
+                probability: probabilities[state_id],
+                score: scores[state_id],
+            })
+            .collect()
+    }
+
+    /// Breaks down the fused score of each of `state_ids` in the same context
+    /// [`predictions`](Self::predictions) would use. Under [`Ranking::Priority`]
+    /// and [`Ranking::Ensemble`] the evidence is the same, but it is not all
+    /// that ordered the suggestions.
+    pub fn explanations(
+        &self,
+        at: i64,
+        current_state_id: Option<usize>,
+        state_ids: &[usize],
+    ) -> Vec<Explanation> {
+        let context = self.make_context(at, current_state_id, self.recent_history.clone());
+        let matches = self.matching_entries(&context);
+        let counter_max = f64::from(self.configuration.counter_max.max(1));
+
+        let evidence = |state_id: usize,
+                        table: Option<usize>,
+                        set: &CandidateSet,
+                        usefulness: u8,
+                        weight: f64| {
+            let with_time = table.is_none_or(|t| t < self.configuration.time_context_tables);
+            let counter = set
+                .values
+                .iter()
+                .find(|c| c.state_id == state_id)
+                .map_or(0, |c| c.counter);
+            TableEvidence {
+                table,
+                history_length: table.map_or(0, |t| self.configuration.history_lengths[t]),
+                hour_bucket: with_time.then_some(context.hour),
+                elapsed_bucket: (with_time && self.configuration.elapsed_context)
+                    .then_some(context.elapsed),
+                usefulness,
+                weight,
+                candidates: set
+                    .ordered()
+                    .iter()
+                    .map(|c| CandidateCount {
+                        state_id: c.state_id,
+                        counter: c.counter,
+                    })
+                    .collect(),
+                contribution: weight * f64::from(counter) / counter_max,
+            }
+        };
+
+        let base = &self.base_table[self.base_index(&context)];
+        state_ids
+            .iter()
+            .map(|&state_id| Explanation {
+                base: evidence(state_id, None, base, 0, 1.0),
+                tables: matches
+                    .iter()
+                    .map(|matched| {
+                        let entry = self.tagged_tables[matched.table][matched.index]
+                            .as_ref()
+                            .unwrap();
+                        evidence(
+                            state_id,
+                            Some(matched.table),
+                            &entry.candidates,
+                            entry.usefulness,
+                            self.fusion_weight(matched.table, entry),
+                        )
+                    })
+                    .collect(),
+                prior: self.recency_scores[state_id] * self.configuration.fusion_prior_weight,
+            })
+            .collect()
+    }
+
+    fn ranking(&self, context: &Context, matches: &[Match], limit: usize) -> Vec<usize> {
+        let current_state_id = context.current_state_id;
+
+        if self.is_ensemble() || self.configuration.ranking == Ranking::Fusion {
+            let ranked = if self.is_ensemble() {
+                let (_, last_seen) = self.fused_scores(context, matches);
+                self.ranked_by(&self.ensemble_scores(context, matches), &last_seen)
+            } else {
+                self.fused_ranking(context, matches)
+            };
             let mut state_ids: Vec<usize> = Vec::with_capacity(limit);
-            for state_id in self.fused_ranking(&context, &matches) {
+            for state_id in ranked {
                 push_unique(&mut state_ids, state_id, current_state_id, limit);
                 if state_ids.len() == limit {
                     break;
@@ -333,7 +1061,7 @@ impl ActivityPredictor {
             return state_ids;
         }
 
-        let base = &self.base_table[self.base_index(&context)];
+        let base = &self.base_table[self.base_index(context)];
         let provider = matches.last();
         let alternate = if matches.len() >= 2 {
             matches.get(matches.len() - 2)
@@ -401,6 +1129,86 @@ impl ActivityPredictor {
         state_ids
     }
 
+    /// The latest entry trained on, which is still running as far as the
+    /// predictor knows.
+    pub fn open_entry(&self) -> Option<OpenEntry> {
+        self.durations.open
+    }
+
+    /// How long `state_id`, started at `started_at` after `previous_state_id`,
+    /// is likely to last in total, given that it is still running at `at`.
+    /// `None` if the state has never been seen to last that long.
+    pub fn duration_estimate(
+        &self,
+        state_id: usize,
+        started_at: i64,
+        previous_state_id: Option<usize>,
+        at: i64,
+    ) -> Option<DurationEstimate> {
+        let hour = hour_bucket(
+            self.local_time(started_at).hour(),
+            self.configuration.hour_bucket,
+        );
+        self.durations
+            .estimate(state_id, hour, previous_state_id, at - started_at)
+    }
+
+    /// The most likely schedule from `at` until `until`, at most `max_blocks`
+    /// long. It starts with the entry already running, ending when its
+    /// duration estimate says, then chains whichever state the predictor ranks
+    /// first, lasting its median duration, and so on. Each step is predicted
+    /// from the simulated history so far, but nothing is learnt from it. Stops
+    /// early at a state that has never been seen to finish; the last block is
+    /// cut off at `until`.
+    pub fn forecast(&self, at: i64, until: i64, max_blocks: usize) -> Vec<ForecastBlock> {
+        let Some(open) = self.durations.open else {
+            return Vec::new();
+        };
+        if until <= at {
+            return Vec::new();
+        }
+
+        let mut simulated = self.clone();
+        let mut blocks = Vec::new();
+        let (mut state_id, mut start, mut previous_state_id) =
+            (open.state_id, open.started_at, open.previous_state_id);
+        let mut probability = None;
+        while blocks.len() < max_blocks {
+            let now = at.max(start);
+            let end = match simulated.duration_estimate(state_id, start, previous_state_id, now) {
+                Some(estimate) => start + estimate.median,
+                // The running entry has outlasted every past run of its state,
+                // so the best guess is that it is about to end.
+                None if blocks.is_empty() => now,
+                None => break,
+            };
+            let end = end.max(now).min(until);
+            blocks.push(ForecastBlock {
+                state_id,
+                start,
+                end,
+                probability,
+            });
+            if end >= until {
+                break;
+            }
+
+            let Some(next) = simulated
+                .scored_predictions(end, Some(state_id), 1)
+                .first()
+                .copied()
+            else {
+                break;
+            };
+            simulated.simulate(next.state_id, end);
+            previous_state_id = Some(state_id);
+            state_id = next.state_id;
+            start = end;
+            probability = Some(next.probability);
+        }
+        blocks
+    }
+
     pub fn train(&mut self, target_state_id: usize, at: i64) {
         let context = self.make_context(
             at,
@@ -420,6 +1228,11 @@ impl ActivityPredictor {
         } else {
             base_top
         };
+        let hour = self.local_time(at).hour();
+        let previous_state_id = self.durations.open.map(|open| open.state_id);
+        self.markov
+            .observe(previous_state_id, target_state_id, hour);
+
         let provider_top = match provider {
             Some((table, index)) => self.tagged_tables[table][index]
                 .as_ref()
@@ -464,7 +1277,35 @@ impl ActivityPredictor {
         self.global_counts[target_state_id] += 1;
         self.recency_scores[target_state_id] += 1.0;
         self.global_last_seen[target_state_id] = self.training_sequence;
-        self.recent_history.push(target_state_id);
+        self.advance(target_state_id, at);
+        self.training_sequence += 1;
+        if self
+            .training_sequence
+            .rem_euclid(self.configuration.usefulness_aging_interval as i64)
+            == 0
+        {
+            self.age_usefulness();
+        }
+    }
+
+    /// Moves the context on to `state_id` starting at `at`, recording how long
+    /// the entry it closes lasted but nothing about the transition.
+    fn advance(&mut self, state_id: usize, at: i64) {
+        let hour = self.move_context(state_id, at);
+        self.durations.start(state_id, at, hour);
+    }
+
+    /// Like [`advance`](Self::advance), but records no duration either, so a
+    /// forecast can step through entries that never happened.
+    fn simulate(&mut self, state_id: usize, at: i64) {
+        let hour = self.move_context(state_id, at);
+        self.durations.move_to(state_id, at, hour);
+    }
+
+    /// Pushes `state_id` onto the recent history and returns the hour bucket
+    /// of `at`.
+    fn move_context(&mut self, state_id: usize, at: i64) -> u32 {
+        self.recent_history.push(state_id);
         let max_history = self
             .configuration
             .history_lengths
@@ -478,14 +1319,7 @@ impl ActivityPredictor {
         }
 
         self.last_event_at = Some(at);
-        self.training_sequence += 1;
-        if self
-            .training_sequence
-            .rem_euclid(self.configuration.usefulness_aging_interval as i64)
-            == 0
-        {
-            self.age_usefulness();
-        }
+        hour_bucket(self.local_time(at).hour(), self.configuration.hour_bucket)
     }
 
     /// On a misprediction, claim up to two entries in tables longer than the one
@@ -538,17 +1372,20 @@ impl ActivityPredictor {
         }
     }
 
+    fn local_time(&self, at: i64) -> DateTime<Z> {
+        self.timezone
+            .timestamp_millis_opt(at)
+            .single()
+            .unwrap_or_else(|| self.timezone.timestamp_nanos(0))
+    }
+
     fn make_context(
         &self,
         at: i64,
         current_state_id: Option<usize>,
         history: Vec<usize>,
     ) -> Context {
-        let local: DateTime<FixedOffset> = self
-            .offset
-            .timestamp_millis_opt(at)
-            .single()
-            .unwrap_or_else(|| self.offset.timestamp_nanos(0));
+        let local = self.local_time(at);
         // Matches Foundation's `Calendar.component(.weekday:)`: 1 = Sunday.
         let weekday = local.weekday().num_days_from_sunday() + 1;
         let elapsed = self
@@ -560,7 +1397,8 @@ impl ActivityPredictor {
                 WeekdayMode::Weekend => u32::from(weekday == 1 || weekday == 7),
                 WeekdayMode::Ignored => 0,
             },
-            hour: local.hour() / self.configuration.hour_bucket.max(1),
+            hour: hour_bucket(local.hour(), self.configuration.hour_bucket),
+            local_hour: local.hour(),
             elapsed,
             current_state_id,
             is_tracking: current_state_id.is_some(),
@@ -635,7 +1473,14 @@ impl ActivityPredictor {
         avalanche(hash)
     }
 
-    fn fused_ranking(&self, context: &Context, matches: &[Match]) -> Vec<usize> {
+    /// Each state's fused score, a weighted sum of its confidence counters across
+    /// the base table and every matching tagged table plus the recency prior,
+    /// along with when any of those tables last saw it.
+    fn fused_scores(
+        &self,
+        context: &Context,
+        matches: &[Match],
+    ) -> ([f64; STATE_COUNT], [i64; STATE_COUNT]) {
         let mut scores = [0.0f64; STATE_COUNT];
         let mut last_seen = [i64::MIN; STATE_COUNT];
         let counter_max = f64::from(self.configuration.counter_max.max(1));
@@ -653,18 +1498,49 @@ impl ActivityPredictor {
             let entry = self.tagged_tables[matched.table][matched.index]
                 .as_ref()
                 .unwrap();
-            let weight = self
-                .configuration
-                .fusion_length_base
-                .powi(matched.table as i32 + 1)
-                * (1.0 + f64::from(entry.usefulness));
-            accumulate(&entry.candidates, weight);
+            accumulate(&entry.candidates, self.fusion_weight(matched.table, entry));
         }
 
         for (score, recency) in scores.iter_mut().zip(self.recency_scores.iter()) {
             *score += recency * self.configuration.fusion_prior_weight;
         }
 
+        (scores, last_seen)
+    }
+
+    fn fusion_weight(&self, table: usize, entry: &TaggedEntry) -> f64 {
+        self.configuration.fusion_length_base.powi(table as i32 + 1)
+            * (1.0 + f64::from(entry.usefulness))
+    }
+
+    /// Whether to rank as [`Ranking::Ensemble`], either because that is the
+    /// configured ranking or because there isn't enough history yet.
+    fn is_ensemble(&self) -> bool {
+        self.configuration.ranking == Ranking::Ensemble
+            || (self.training_sequence as usize) < self.configuration.cold_start_entries
+    }
+
+    /// Each state's share of the fused score, blended with its
+    /// [`MarkovHourRanker`] estimate. Both are taken over every state but the
+    /// current one, so the blend sums to 1 too.
+    fn ensemble_scores(&self, context: &Context, matches: &[Match]) -> [f64; STATE_COUNT] {
+        let current_state_id = context.current_state_id;
+        let (fused, _) = self.fused_scores(context, matches);
+        let fused = shares(&fused, current_state_id);
+        let markov = shares(
+            &self.markov.estimate(current_state_id, context.local_hour),
+            current_state_id,
+        );
+        let weight = self.configuration.ensemble_markov_weight;
+        std::array::from_fn(|s| (1.0 - weight) * fused[s] + weight * markov[s])
+    }
+
+    fn fused_ranking(&self, context: &Context, matches: &[Match]) -> Vec<usize> {
+        let (scores, last_seen) = self.fused_scores(context, matches);
+        self.ranked_by(&scores, &last_seen)
+    }
+
+    fn ranked_by(&self, scores: &[f64; STATE_COUNT], last_seen: &[i64; STATE_COUNT]) -> Vec<usize> {
         let mut ranked: Vec<usize> = (0..STATE_COUNT).collect();
         ranked.sort_by(|&a, &b| {
             scores[b]
@@ -678,7 +1554,15 @@ impl ActivityPredictor {
     }
 }
 
-fn elapsed_bucket(milliseconds: i64) -> u32 {
+/// Which `width`-hour block of the day `hour` falls in. A width of 0 is treated
+/// as 1.
+pub fn hour_bucket(hour: u32, width: u32) -> u32 {
+    hour / width.max(1)
+}
+
+/// Log-scale bucket of a duration: 0 for nothing, then under 5 minutes, 15, 30,
+/// 1 hour, 2, 4, 8, and 8 hours or more as bucket 8.
+pub fn elapsed_bucket(milliseconds: i64) -> u32 {
     const MINUTE: i64 = 60_000;
     match milliseconds {
         ..=0 => 0,
@@ -693,6 +1577,23 @@ fn elapsed_bucket(milliseconds: i64) -> u32 {
     }
 }
 
+/// Each state's share of the total over every state but `current_state_id`,
+/// which gets 0. Equal shares if there is nothing to go on.
+fn shares(scores: &[f64; STATE_COUNT], current_state_id: Option<usize>) -> [f64; STATE_COUNT] {
+    let candidates = (0..STATE_COUNT).filter(|&s| Some(s) != current_state_id);
+    let candidate_count = candidates.clone().count();
+    let total: f64 = candidates.map(|s| scores[s]).sum();
+    std::array::from_fn(|s| {
+        if Some(s) == current_state_id {
+            0.0
+        } else if total > 0.0 {
+            scores[s] / total
+        } else {
+            1.0 / candidate_count as f64
+        }
+    })
+}
+
 fn push_unique(
     state_ids: &mut Vec<usize>,
     state_id: usize,
@@ -778,6 +1679,7 @@ mod tests {
                 history_lengths: vec![1, 2, 4, 8],
                 base_table_size: 1_024,
                 tagged_table_size: 1_024,
+                cold_start_entries: 0,
                 ..Configuration::default()
             },
         );
@@ -807,6 +1709,49 @@ mod tests {
         assert_eq!(predictor.predictions(last, None, 3).first(), Some(&2));
     }
 
+    #[test]
+    fn restored_snapshot_trains_on_like_the_original() {
+        let states: Vec<usize> = std::iter::repeat_n([0, 4, 1, 6, 7], 30).flatten().collect();
+        let entries = make_entries(&states);
+        let (head, tail) = entries.split_at(100);
+
+        let full = ActivityPredictor::new(&entries, utc(), Configuration::default());
+        let snapshot = ActivityPredictor::new(head, utc(), Configuration::default()).snapshot();
+        let mut resumed =
+            ActivityPredictor::restore(snapshot.clone(), utc(), Configuration::default()).unwrap();
+        for entry in tail {
+            resumed.train(entry.state_id, entry.start_timestamp);
+        }
+
+        let at = entries.last().unwrap().start_timestamp + 60_000;
+        for current in [None, Some(0), Some(7)] {
+            assert_eq!(
+                resumed.predictions(at, current, 5),
+                full.predictions(at, current, 5)
+            );
+        }
+
+        let smaller = Configuration {
+            base_table_size: 256,
+            ..Configuration::default()
+        };
+        assert!(ActivityPredictor::restore(snapshot, utc(), smaller).is_none());
+    }
+
+    #[test]
+    fn named_zones_follow_dst() {
+        let hour_at = |at: i64| {
+            let predictor =
+                ActivityPredictor::new(&[], chrono_tz::Europe::London, Configuration::legacy());
+            predictor.make_context(at, None, Vec::new()).hour
+        };
+        // 08:30 UTC is 08:30 in London in January and 09:30 in July.
+        let winter = utc().with_ymd_and_hms(2026, 1, 15, 8, 30, 0).unwrap();
+        let summer = utc().with_ymd_and_hms(2026, 7, 15, 8, 30, 0).unwrap();
+        assert_eq!(hour_at(winter.timestamp_millis()), 8);
+        assert_eq!(hour_at(summer.timestamp_millis()), 9);
+    }
+
     #[test]
     fn uses_weekday_and_hour_context() {
         let utc = utc();
@@ -880,7 +1825,11 @@ mod tests {
             .flatten()
             .collect();
         let entries = make_entries(&states);
-        let predictor = ActivityPredictor::new(&entries, utc(), Configuration::default());
+        let fusion = Configuration {
+            cold_start_entries: 0,
+            ..Configuration::default()
+        };
+        let predictor = ActivityPredictor::new(&entries, utc(), fusion);
         let last = entries.last().unwrap().start_timestamp + 60_000;
         let predictions = predictor.predictions(last, Some(3), 3);
 
@@ -889,6 +1838,306 @@ mod tests {
         assert!(predictions.contains(&1));
     }
 
+    #[test]
+    fn markov_hour_ranker_backs_off_to_any_hour() {
+        let mut ranker = MarkovHourRanker::new(4);
+        // After 0: 1 twice in the morning, 2 three times in the evening.
+        for (next, hour) in [(1, 9), (1, 10), (2, 20), (2, 21), (2, 22)] {
+            ranker.observe(None, 0, hour);
+            ranker.observe(Some(0), next, hour);
+        }
+
+        assert_eq!(ranker.rank(Some(0), 8, 2), vec![1, 2]);
+        assert_eq!(ranker.rank(Some(0), 20, 2), vec![2, 1]);
+        // Nothing after 0 at night, so any hour decides.
+        assert_eq!(ranker.rank(Some(0), 2, 2), vec![2, 1]);
+        assert_eq!(ranker.rank(None, 2, 1), vec![0]);
+
+        let estimate = ranker.estimate(Some(0), 8);
+        assert!((estimate.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(estimate[1] > estimate[2] && estimate[2] > estimate[3]);
+    }
+
+    #[test]
+    fn cold_start_ranks_as_ensemble() {
+        let states: Vec<usize> = std::iter::repeat_n([0, 1, 2, 0, 1, 3], 10)
+            .flatten()
+            .collect();
+        let entries = make_entries(&states);
+        let last = entries.last().unwrap().start_timestamp + 60_000;
+        let scored = |configuration| {
+            ActivityPredictor::new(&entries, utc(), configuration).scored_predictions(
+                last,
+                Some(3),
+                STATE_COUNT,
+            )
+        };
+        let fusion = Configuration {
+            cold_start_entries: 0,
+            ..Configuration::default()
+        };
+        let ensemble = scored(Configuration {
+            ranking: Ranking::Ensemble,
+            ..fusion.clone()
+        });
+
+        assert_eq!(
+            scored(Configuration {
+                cold_start_entries: entries.len() + 1,
+                ..fusion.clone()
+            }),
+            ensemble
+        );
+        assert_eq!(
+            scored(Configuration {
+                cold_start_entries: entries.len(),
+                ..fusion.clone()
+            }),
+            scored(fusion)
+        );
+        let total: f64 = ensemble.iter().map(|p| p.probability).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert_eq!(ensemble[0].state_id, 0);
+    }
+
+    #[test]
+    fn scored_predictions_are_normalised_and_ordered_like_plain_ones() {
+        let states: Vec<usize> = std::iter::repeat_n([0, 1, 2, 0, 1, 3], 30)
+            .flatten()
+            .collect();
+        let entries = make_entries(&states);
+        let fusion = Configuration {
+            cold_start_entries: 0,
+            ..Configuration::default()
+        };
+        let predictor = ActivityPredictor::new(&entries, utc(), fusion.clone());
+        let last = entries.last().unwrap().start_timestamp + 60_000;
+
+        let scored = predictor.scored_predictions(last, Some(3), STATE_COUNT);
+        let ids: Vec<usize> = scored.iter().map(|p| p.state_id).collect();
+        assert_eq!(ids, predictor.predictions(last, Some(3), STATE_COUNT));
+        let total: f64 = scored.iter().map(|p| p.probability).sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert!(scored.windows(2).all(|w| w[0].score >= w[1].score));
+        assert!(scored[0].probability > 0.5);
+
+        let untrained = ActivityPredictor::new(&[], utc(), fusion);
+        let cold = untrained.scored_predictions(last, Some(3), 3);
+        assert!(
+            cold.iter()
+                .all(|p| (p.probability - 1.0 / (STATE_COUNT - 1) as f64).abs() < 1e-9)
+        );
+    }
+
+    #[test]
+    fn explanations_add_up_to_the_fused_score() {
+        let states: Vec<usize> = std::iter::repeat_n([0, 1, 2, 0, 1, 3], 30)
+            .flatten()
+            .collect();
+        let entries = make_entries(&states);
+        let predictor = ActivityPredictor::new(&entries, utc(), Configuration::default());
+        let last = entries.last().unwrap().start_timestamp + 60_000;
+
+        let scored = predictor.scored_predictions(last, Some(3), 3);
+        let ids: Vec<usize> = scored.iter().map(|p| p.state_id).collect();
+        let explanations = predictor.explanations(last, Some(3), &ids);
+        assert_eq!(explanations.len(), 3);
+        for (prediction, explanation) in scored.iter().zip(&explanations) {
+            let total = explanation.base.contribution
+                + explanation
+                    .tables
+                    .iter()
+                    .map(|t| t.contribution)
+                    .sum::<f64>()
+                + explanation.prior;
+            assert!((total - prediction.score).abs() < 1e-9);
+        }
+
+        let top = &explanations[0];
+        assert!(!top.tables.is_empty());
+        assert!(
+            top.tables
+                .windows(2)
+                .all(|w| w[0].history_length < w[1].history_length)
+        );
+        assert_eq!(top.base.history_length, 0);
+        let hour = utc().timestamp_millis_opt(last).unwrap().hour();
+        assert_eq!(top.base.hour_bucket, Some(hour_bucket(hour, 4)));
+        assert_eq!(top.base.elapsed_bucket, None);
+    }
+
+    #[test]
+    fn estimates_durations_from_the_most_specific_context() {
+        const MINUTE: i64 = 60_000;
+        let mut entries = Vec::new();
+        let mut at = 1_700_000_000_000;
+        for i in 0..40 {
+            // Study runs 20 minutes, 40 every fourth time; breaks run 10.
+            let (state_id, length) = if i % 2 == 0 {
+                (0, if i % 8 == 0 { 40 } else { 20 })
+            } else {
+                (4, 10)
+            };
+            entries.push(TrainingEntry {
+                state_id,
+                start_timestamp: at,
+            });
+            at += length * MINUTE;
+        }
+        let configuration = Configuration {
+            hour_bucket: 24,
+            ..Configuration::default()
+        };
+        let predictor = ActivityPredictor::new(&entries, utc(), configuration);
+
+        let open = predictor.open_entry().unwrap();
+        assert_eq!(open.state_id, 4);
+        assert_eq!(open.previous_state_id, Some(0));
+
+        let study = predictor.duration_estimate(0, at, Some(4), at).unwrap();
+        assert_eq!(study.context, DurationContext::History);
+        assert_eq!(study.median, 20 * MINUTE);
+        assert_eq!(study.p75, 20 * MINUTE);
+        assert_eq!(study.samples, 19);
+
+        // Once past 20 minutes only the long runs are left, too few after a
+        // break alone, so it backs off to every study run.
+        let overrun = predictor
+            .duration_estimate(0, at, Some(4), at + 25 * MINUTE)
+            .unwrap();
+        assert_eq!(overrun.median, 40 * MINUTE);
+        assert_eq!(overrun.context, DurationContext::Hour);
+        assert_eq!(overrun.samples, 5);
+
+        let after_work = predictor.duration_estimate(0, at, Some(1), at).unwrap();
+        assert_eq!(after_work.context, DurationContext::Hour);
+        assert!(
+            predictor
+                .duration_estimate(0, at, Some(4), at + 60 * MINUTE)
+                .is_none()
+        );
+        assert!(predictor.duration_estimate(2, at, None, at).is_none());
+    }
+
+    #[test]
+    fn forecast_steps_record_no_durations() {
+        const MINUTE: i64 = 60_000;
+        let mut entries = Vec::new();
+        let mut at = 1_700_000_000_000;
+        for i in 0..40 {
+            let (state_id, length) = if i % 2 == 0 { (0, 20) } else { (4, 10) };
+            entries.push(TrainingEntry {
+                state_id,
+                start_timestamp: at,
+            });
+            at += length * MINUTE;
+        }
+        let configuration = Configuration {
+            hour_bucket: 24,
+            ..Configuration::default()
+        };
+        let predictor = ActivityPredictor::new(&entries, utc(), configuration);
+
+        // Study has only ever followed a break. Walking a forecast through
+        // Study after Work, however often, must not make that a context.
+        let mut simulated = predictor.clone();
+        for _ in 0..=MIN_DURATION_SAMPLES {
+            simulated.simulate(1, at);
+            simulated.simulate(0, at + 30 * MINUTE);
+            at += 50 * MINUTE;
+        }
+        simulated.simulate(1, at);
+        let study = simulated.duration_estimate(0, at, Some(1), at).unwrap();
+        assert_eq!(study.context, DurationContext::Hour);
+        assert_eq!(study.samples, 20);
+        assert!(simulated.duration_estimate(1, at, None, at).is_none());
+    }
+
+    #[test]
+    fn forecast_chains_predictions_and_durations() {
+        const MINUTE: i64 = 60_000;
+        // Work 50 minutes, a 10-minute break, round and round.
+        let mut entries = Vec::new();
+        let mut at = 1_700_000_000_000;
+        for i in 0..60 {
+            let (state_id, length) = if i % 2 == 0 { (1, 50) } else { (6, 10) };
+            entries.push(TrainingEntry {
+                state_id,
+                start_timestamp: at,
+            });
+            at += length * MINUTE;
+        }
+        let started = entries.last().unwrap().start_timestamp;
+        let predictor = ActivityPredictor::new(&entries, utc(), Configuration::default());
+
+        let now = started + 5 * MINUTE;
+        let blocks = predictor.forecast(now, now + 150 * MINUTE, 50);
+        let schedule: Vec<(usize, i64, i64)> = blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.state_id,
+                    (b.start - started) / MINUTE,
+                    (b.end - started) / MINUTE,
+                )
+            })
+            .collect();
+        assert_eq!(
+            schedule,
+            [
+                (6, 0, 10),
+                (1, 10, 60),
+                (6, 60, 70),
+                (1, 70, 120),
+                (6, 120, 130),
+                (1, 130, 155)
+            ]
+        );
+        assert_eq!(blocks[0].probability, None);
+        assert!(blocks[1..].iter().all(|b| b.probability.unwrap() > 0.5));
+
+        assert_eq!(predictor.forecast(now, now + 150 * MINUTE, 2).len(), 2);
+        assert!(predictor.forecast(now, now, 50).is_empty());
+        let untrained = ActivityPredictor::new(&[], utc(), Configuration::default());
+        assert!(untrained.forecast(now, now + MINUTE, 50).is_empty());
+    }
+
+    #[test]
+    fn validation_rejects_configurations_that_would_panic() {
+        assert_eq!(Configuration::default().validate(), Ok(()));
+        assert_eq!(Configuration::legacy().validate(), Ok(()));
+        assert_eq!(Configuration::default().preset(), Some(Preset::Default));
+        assert_eq!(Configuration::legacy().preset(), Some(Preset::Legacy));
+
+        let invalid = [
+            Configuration {
+                base_table_size: 0,
+                ..Configuration::default()
+            },
+            Configuration {
+                usefulness_aging_interval: 0,
+                ..Configuration::default()
+            },
+            Configuration {
+                history_lengths: vec![1, 0],
+                ..Configuration::default()
+            },
+            Configuration {
+                recency_decay: f64::NAN,
+                ..Configuration::default()
+            },
+        ];
+        for configuration in invalid {
+            assert!(configuration.validate().is_err());
+            assert_eq!(configuration.preset(), None);
+        }
+
+        // Partial configurations fill in from the default.
+        let partial: Configuration = serde_json::from_str(r#"{"hour_bucket": 2}"#).unwrap();
+        assert_eq!(partial.hour_bucket, 2);
+        assert_eq!(partial.ranking, Ranking::Fusion);
+    }
+
     #[test]
     fn legacy_configuration_keeps_priority_ranking() {
         assert_eq!(Configuration::legacy().ranking, Ranking::Priority);
@@ -0,0 +1,374 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Long-lived predictors, one per timezone and configuration. Each is built once
+//! from the tail of the log, then trained forward over whatever has been
+//! appended since it was last used. Only a rewrite of history (an update, an
+//! import or a forced length) makes it start over. Tables are written to the
+//! `predictors` tree so a restart picks up where it left off.
+//!
+//! Each predictor has its own lock, held while it trains, so a first request for
+//! one zone or configuration doesn't hold up requests for the others.
+
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    constants::STATE_COUNT,
+    predictor::{ActivityPredictor, Configuration, PredictorSnapshot, TrainingEntry},
+    timezone::Zone,
+    utils::{
+        get_history_version, get_length, is_valid_timestamp, log_corrupt_entry, try_read_from_value,
+    },
+};
+
+/// More than this many zone/configuration pairs and the least recently used
+/// one is dropped, from memory and from disk.
+const MAX_CACHED_PREDICTORS: usize = 8;
+
+struct Cached {
+    predictor: ActivityPredictor<Zone>,
+    history_version: u64,
+    /// Log length the predictor has been trained up to.
+    trained_to: u64,
+    trained_on: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    history_version: u64,
+    trained_to: u64,
+    trained_on: usize,
+    snapshot: PredictorSnapshot,
+}
+
+/// A predictor's place in the cache. Empty until the first use loads or trains
+/// it.
+struct Slot {
+    predictor: Arc<Mutex<Option<Cached>>>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Slots {
+    by_key: HashMap<String, Slot>,
+    uses: u64,
+}
+
+pub struct PredictorCache {
+    tree: Tree,
+    /// Only held to look a slot up or evict one, never while training.
+    slots: Mutex<Slots>,
+}
+
+impl PredictorCache {
+    pub fn new(tree: Tree) -> Self {
+        Self {
+            tree,
+            slots: Mutex::default(),
+        }
+    }
+
+    /// Runs `f` on the predictor for `zone` and `configuration`, first bringing
+    /// it up to date with the log. `f` also gets how many entries it has been
+    /// trained on in total.
+    pub fn with_predictor<R>(
+        &self,
+        zone: Zone,
+        configuration: Configuration,
+        events: &Tree,
+        meta: &Tree,
+        f: impl FnOnce(&ActivityPredictor<Zone>, usize) -> R,
+    ) -> R {
+        let key = cache_key(zone, &configuration);
+        let slot = self.slot(&key);
+        let mut slot = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Read under the predictor's lock, so a request that waited for another
+        // to train sees everything that one trained on.
+        let history_version = get_history_version(meta);
+        let length = get_length(meta);
+
+        if slot.is_none() {
+            *slot = self.load(&key, zone, &configuration);
+        }
+
+        let mut changed = false;
+        let stale = slot.as_ref().is_none_or(|cached| {
+            cached.history_version != history_version || cached.trained_to > length
+        });
+        if stale {
+            let start = length.saturating_sub(configuration.maximum_training_entries as u64);
+            let entries = read_training_entries(events, start, length);
+            *slot = Some(Cached {
+                predictor: ActivityPredictor::new(&entries, zone, configuration),
+                history_version,
+                trained_to: length,
+                trained_on: entries.len(),
+            });
+            changed = true;
+        }
+
+        let cached = slot.as_mut().expect("just trained");
+        if cached.trained_to < length {
+            let entries = read_training_entries(events, cached.trained_to, length);
+            for entry in &entries {
+                cached
+                    .predictor
+                    .train(entry.state_id, entry.start_timestamp);
+            }
+            cached.trained_to = length;
+            cached.trained_on += entries.len();
+            changed = true;
+        }
+
+        let result = f(&cached.predictor, cached.trained_on);
+        if changed {
+            self.store(&key, cached);
+        }
+        result
+    }
+
+    /// Whether the predictor for `zone` and `configuration` is in memory and
+    /// built from the current history, so using it costs no more than catching
+    /// up on entries appended since. `false` while it is being trained.
+    pub fn is_trained(&self, zone: Zone, configuration: &Configuration, meta: &Tree) -> bool {
+        let key = cache_key(zone, configuration);
+        let Some(slot) = self
+            .slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .by_key
+            .get(&key)
+            .map(|slot| slot.predictor.clone())
+        else {
+            return false;
+        };
+        let Ok(slot) = slot.try_lock() else {
+            return false;
+        };
+        slot.as_ref()
+            .is_some_and(|cached| cached.history_version == get_history_version(meta))
+    }
+
+    /// The slot for `key`, made the most recently used. Adding one may evict the
+    /// least recently used other slot.
+    fn slot(&self, key: &str) -> Arc<Mutex<Option<Cached>>> {
+        let mut slots = self
+            .slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        slots.uses += 1;
+        let uses = slots.uses;
+        let slot = slots.by_key.entry(key.to_string()).or_insert_with(|| Slot {
+            predictor: Arc::default(),
+            last_used: 0,
+        });
+        slot.last_used = uses;
+        let predictor = slot.predictor.clone();
+        self.evict(&mut slots);
+        predictor
+    }
+
+    fn load(&self, key: &str, zone: Zone, configuration: &Configuration) -> Option<Cached> {
+        let bytes = self.tree.get(key.as_bytes()).ok()??;
+        let stored: Stored = match serde_json::from_slice(&bytes) {
+            Ok(stored) => stored,
+            Err(err) => {
+                eprintln!("predictor_cache: discarding unreadable predictor {key}: {err}");
+                return None;
+            }
+        };
+        let predictor = ActivityPredictor::restore(stored.snapshot, zone, configuration.clone())?;
+        Some(Cached {
+            predictor,
+            history_version: stored.history_version,
+            trained_to: stored.trained_to,
+            trained_on: stored.trained_on,
+        })
+    }
+
+    fn store(&self, key: &str, cached: &Cached) {
+        let stored = Stored {
+            history_version: cached.history_version,
+            trained_to: cached.trained_to,
+            trained_on: cached.trained_on,
+            snapshot: cached.predictor.snapshot(),
+        };
+        // Losing a snapshot only costs a retrain after the next restart.
+        let written = serde_json::to_vec(&stored)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| {
+                // Checked under the slots lock, so a predictor evicted while it
+                // was training doesn't leave a snapshot behind.
+                let slots = self
+                    .slots
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                if !slots.by_key.contains_key(key) {
+                    return Ok(());
+                }
+                self.tree
+                    .insert(key.as_bytes(), bytes)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = written {
+            eprintln!("predictor_cache: failed to store predictor {key}: {err}");
+        }
+    }
+
+    fn evict(&self, slots: &mut Slots) {
+        while slots.by_key.len() > MAX_CACHED_PREDICTORS {
+            let Some(oldest) = slots
+                .by_key
+                .iter()
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                return;
+            };
+            slots.by_key.remove(&oldest);
+            if let Err(err) = self.tree.remove(oldest.as_bytes()) {
+                eprintln!("predictor_cache: failed to remove predictor {oldest}: {err}");
+            }
+        }
+    }
+}
+
+/// Like [`PredictorCache::with_predictor`], but trains a predictor for this call
+/// alone and drops it after. For one-off configurations, which would otherwise
+/// evict the ones in use and leave a snapshot behind.
+pub fn with_uncached_predictor<R>(
+    zone: Zone,
+    configuration: Configuration,
+    events: &Tree,
+    meta: &Tree,
+    f: impl FnOnce(&ActivityPredictor<Zone>, usize) -> R,
+) -> R {
+    let length = get_length(meta);
+    let start = length.saturating_sub(configuration.maximum_training_entries as u64);
+    let entries = read_training_entries(events, start, length);
+    let predictor = ActivityPredictor::new(&entries, zone, configuration);
+    f(&predictor, entries.len())
+}
+
+fn cache_key(zone: Zone, configuration: &Configuration) -> String {
+    let configuration = serde_json::to_string(configuration).unwrap_or_default();
+    format!("{}|{configuration}", zone.name())
+}
+
+/// Entries `[start, end)` of the log, dropping (and logging) any that can't be
+/// placed in the training sequence, so one corrupt row can't skew every
+/// prediction.
+fn read_training_entries(events: &Tree, start: u64, end: u64) -> Vec<TrainingEntry> {
+    let mut entries = Vec::with_capacity(end.saturating_sub(start) as usize);
+    for i in start..end {
+        let Some((state_id, timestamp)) = try_read_from_value(events, i) else {
+            eprintln!("predictor_cache: skipping unreadable entry at index {i}");
+            continue;
+        };
+        if state_id as usize >= STATE_COUNT || !is_valid_timestamp(timestamp) {
+            log_corrupt_entry("predictor_cache", i, state_id, timestamp);
+            continue;
+        }
+        entries.push(TrainingEntry {
+            state_id: state_id as usize,
+            start_timestamp: timestamp,
+        });
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{bump_history_version, incr_length, to_ivec};
+    use chrono::FixedOffset;
+    use sled::transaction::TransactionResult;
+
+    fn append(events: &Tree, meta: &Tree, state: u8, at: i64) {
+        let mut bytes = [0u8; 9];
+        bytes[0] = state;
+        bytes[1..].copy_from_slice(&at.to_ne_bytes());
+        events.insert(to_ivec(get_length(meta)), &bytes).unwrap();
+        incr_length(meta);
+    }
+
+    #[test]
+    fn trains_forward_and_survives_a_restart() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let (events, meta) = (
+            db.open_tree("events").unwrap(),
+            db.open_tree("meta").unwrap(),
+        );
+        let zone = Zone::Fixed(FixedOffset::east_opt(0).unwrap());
+        let states = [0u8, 4, 1, 6, 7];
+        for i in 0..100 {
+            append(
+                &events,
+                &meta,
+                states[i % 5],
+                1_700_000_000_000 + i as i64 * 600_000,
+            );
+        }
+
+        let cache = PredictorCache::new(db.open_tree("predictors").unwrap());
+        let trained_on = |cache: &PredictorCache| {
+            cache.with_predictor(zone, Configuration::default(), &events, &meta, |_, n| n)
+        };
+        assert_eq!(trained_on(&cache), 100);
+
+        append(&events, &meta, 0, 1_700_000_000_000 + 100 * 600_000);
+        assert_eq!(trained_on(&cache), 101);
+
+        // A fresh cache over the same tree resumes rather than retraining.
+        let restarted = PredictorCache::new(db.open_tree("predictors").unwrap());
+        append(&events, &meta, 4, 1_700_000_000_000 + 101 * 600_000);
+        assert_eq!(trained_on(&restarted), 102);
+
+        // Rewriting history starts over from the tail of the log.
+        let bumped: TransactionResult<()> =
+            meta.transaction(|meta| Ok(bump_history_version(meta)?));
+        bumped.unwrap();
+        let configuration = Configuration {
+            maximum_training_entries: 50,
+            ..Configuration::default()
+        };
+        let rebuilt = restarted.with_predictor(zone, configuration, &events, &meta, |_, n| n);
+        assert_eq!(rebuilt, 50);
+        assert_eq!(trained_on(&restarted), 102);
+
+        // A one-off configuration leaves neither a slot nor a snapshot behind.
+        let configuration = Configuration {
+            maximum_training_entries: 20,
+            ..Configuration::default()
+        };
+        let one_off =
+            with_uncached_predictor(zone, configuration.clone(), &events, &meta, |_, n| n);
+        assert_eq!(one_off, 20);
+        assert!(!restarted.is_trained(zone, &configuration, &meta));
+        assert!(
+            restarted
+                .tree
+                .get(cache_key(zone, &configuration))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
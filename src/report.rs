@@ -0,0 +1,495 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Periodic review documents. The handler gathers a [`Report`] from the other
+//! analytics; this module only lays it out as Markdown or HTML.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use crate::{
+    analytics::{Deviation, Period},
+    constants::ALL_STATES_DETAILS,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub period: Period,
+    pub label: String,
+    pub from: i64,
+    pub to: i64,
+    pub tz: String,
+    /// Label of the period compared against.
+    pub previous_label: String,
+    /// The period isn't over yet, so only as much of the previous one as has
+    /// elapsed of this one is compared against.
+    pub to_date: bool,
+    pub tracked: i64,
+    pub previous_tracked: i64,
+    pub states: Vec<StateLine>,
+    /// Empty unless asked for.
+    pub groups: Vec<GroupLine>,
+    /// Longest sessions started in the period, Sleep aside.
+    pub top_sessions: Vec<SessionLine>,
+    pub sleep: Option<SleepSummary>,
+    pub habits: Vec<HabitLine>,
+    pub anomalies: Vec<AnomalyLine>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StateLine {
+    pub state: usize,
+    pub emoji: &'static str,
+    pub name: &'static str,
+    pub colour: &'static str,
+    pub total: i64,
+    pub previous: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GroupLine {
+    pub name: &'static str,
+    pub colour: &'static str,
+    pub total: i64,
+    pub previous: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionLine {
+    pub state: usize,
+    pub start: i64,
+    /// Local weekday and time the session started, e.g. `Tue 09:30`.
+    pub start_local: String,
+    pub length: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SleepSummary {
+    pub nights: usize,
+    pub mean_duration: i64,
+    pub mean_bedtime: Option<String>,
+    pub mean_wake: Option<String>,
+    pub regularity_index: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HabitLine {
+    pub name: String,
+    pub passed: usize,
+    pub failed: usize,
+    pub current_streak: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AnomalyLine {
+    pub date: String,
+    pub deviations: Vec<Deviation>,
+}
+
+impl Report {
+    fn title(&self) -> String {
+        let kind = match self.period {
+            Period::Day => "Daily",
+            Period::Week => "Weekly",
+            Period::Month => "Monthly",
+            Period::Year => "Yearly",
+        };
+        format!("{kind} review: {}", self.label)
+    }
+
+    fn previous(&self) -> String {
+        if self.to_date {
+            format!("{} to date", self.previous_label)
+        } else {
+            self.previous_label.clone()
+        }
+    }
+
+    /// States with any time in either period, most time first.
+    fn active_states(&self) -> Vec<&StateLine> {
+        let mut states: Vec<&StateLine> = self
+            .states
+            .iter()
+            .filter(|line| line.total > 0 || line.previous > 0)
+            .collect();
+        states.sort_by_key(|line| std::cmp::Reverse(line.total));
+        states
+    }
+}
+
+/// `4h 05m`, or `35m` under an hour. Rounded to the minute.
+pub fn format_duration(ms: i64) -> String {
+    let minutes = (ms.abs() + 30_000) / 60_000;
+    let sign = if ms < 0 && minutes > 0 { "-" } else { "" };
+    if minutes < 60 {
+        format!("{sign}{minutes}m")
+    } else {
+        format!("{sign}{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// `+1h 05m (+9%)`, leaving the percentage out when there is nothing to compare with.
+fn format_change(current: i64, previous: i64) -> String {
+    let delta = current - previous;
+    let sign = if delta > 0 { "+" } else { "" };
+    if previous == 0 {
+        format!("{sign}{}", format_duration(delta))
+    } else {
+        let percent = delta as f64 / previous as f64 * 100.0;
+        format!("{sign}{} ({sign}{percent:.0}%)", format_duration(delta))
+    }
+}
+
+fn describe_deviation(deviation: &Deviation) -> String {
+    let detail = &ALL_STATES_DETAILS[deviation.state];
+    format!(
+        "{} {} {} (usually {})",
+        detail.emoji,
+        detail.name,
+        format_duration(deviation.total),
+        format_duration(deviation.median)
+    )
+}
+
+pub fn render_markdown(report: &Report) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}\n", report.title());
+    let _ = writeln!(
+        out,
+        "Tracked {}, against {} in {}.\n",
+        format_duration(report.tracked),
+        format_duration(report.previous_tracked),
+        report.previous()
+    );
+
+    let states = report.active_states();
+    if !states.is_empty() {
+        let _ = writeln!(out, "## Time by state\n");
+        let _ = writeln!(out, "| | State | Time | vs {} |", report.previous());
+        let _ = writeln!(out, "|---|---|---:|---:|");
+        for line in states {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                line.emoji,
+                line.name,
+                format_duration(line.total),
+                format_change(line.total, line.previous)
+            );
+        }
+        out.push('\n');
+    }
+
+    if !report.groups.is_empty() {
+        let _ = writeln!(out, "## Time by group\n");
+        let _ = writeln!(out, "| Group | Time | vs {} |", report.previous());
+        let _ = writeln!(out, "|---|---:|---:|");
+        for line in &report.groups {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} |",
+                line.name,
+                format_duration(line.total),
+                format_change(line.total, line.previous)
+            );
+        }
+        out.push('\n');
+    }
+
+    if !report.top_sessions.is_empty() {
+        let _ = writeln!(out, "## Longest sessions\n");
+        for (i, session) in report.top_sessions.iter().enumerate() {
+            let detail = &ALL_STATES_DETAILS[session.state];
+            let _ = writeln!(
+                out,
+                "{}. {} {}: {}, from {}",
+                i + 1,
+                detail.emoji,
+                detail.name,
+                format_duration(session.length),
+                session.start_local
+            );
+        }
+        out.push('\n');
+    }
+
+    if let Some(sleep) = &report.sleep {
+        let _ = writeln!(out, "## Sleep\n");
+        let _ = writeln!(
+            out,
+            "- {} nights, {} on average",
+            sleep.nights,
+            format_duration(sleep.mean_duration)
+        );
+        if let (Some(bedtime), Some(wake)) = (&sleep.mean_bedtime, &sleep.mean_wake) {
+            let _ = writeln!(out, "- Usually asleep at {bedtime}, awake at {wake}");
+        }
+        if let Some(index) = sleep.regularity_index {
+            let _ = writeln!(out, "- Regularity index {index:.0}");
+        }
+        out.push('\n');
+    }
+
+    if !report.habits.is_empty() {
+        let _ = writeln!(out, "## Habits\n");
+        let _ = writeln!(out, "| Habit | Passed | Failed | Streak |");
+        let _ = writeln!(out, "|---|---:|---:|---:|");
+        for habit in &report.habits {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                habit.name.replace('|', "\\|"),
+                habit.passed,
+                habit.failed,
+                habit.current_streak
+            );
+        }
+        out.push('\n');
+    }
+
+    if !report.anomalies.is_empty() {
+        let _ = writeln!(out, "## Unusual days\n");
+        for day in &report.anomalies {
+            let details: Vec<String> = day.deviations.iter().map(describe_deviation).collect();
+            let _ = writeln!(out, "- **{}**: {}", day.date, details.join("; "));
+        }
+        out.push('\n');
+    }
+
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn render_html(report: &Report) -> String {
+    let title = escape_html(&report.title());
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>"
+    );
+    let _ = writeln!(out, "<h1>{title}</h1>");
+    let _ = writeln!(
+        out,
+        "<p>Tracked {}, against {} in {}.</p>",
+        format_duration(report.tracked),
+        format_duration(report.previous_tracked),
+        escape_html(&report.previous())
+    );
+
+    let states = report.active_states();
+    if !states.is_empty() {
+        let _ = writeln!(out, "<h2>Time by state</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th></th><th>State</th><th>Time</th><th>vs {}</th></tr>",
+            escape_html(&report.previous())
+        );
+        for line in states {
+            let _ = writeln!(
+                out,
+                "<tr><td style=\"color: {}\">&#9632; {}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                line.colour,
+                line.emoji,
+                line.name,
+                format_duration(line.total),
+                format_change(line.total, line.previous)
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    if !report.groups.is_empty() {
+        let _ = writeln!(out, "<h2>Time by group</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Group</th><th>Time</th><th>vs {}</th></tr>",
+            escape_html(&report.previous())
+        );
+        for line in &report.groups {
+            let _ = writeln!(
+                out,
+                "<tr><td style=\"color: {}\">&#9632; {}</td><td>{}</td><td>{}</td></tr>",
+                line.colour,
+                line.name,
+                format_duration(line.total),
+                format_change(line.total, line.previous)
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    if !report.top_sessions.is_empty() {
+        let _ = writeln!(out, "<h2>Longest sessions</h2>\n<ol>");
+        for session in &report.top_sessions {
+            let detail = &ALL_STATES_DETAILS[session.state];
+            let _ = writeln!(
+                out,
+                "<li>{} {}: {}, from {}</li>",
+                detail.emoji,
+                detail.name,
+                format_duration(session.length),
+                escape_html(&session.start_local)
+            );
+        }
+        let _ = writeln!(out, "</ol>");
+    }
+
+    if let Some(sleep) = &report.sleep {
+        let _ = writeln!(out, "<h2>Sleep</h2>\n<ul>");
+        let _ = writeln!(
+            out,
+            "<li>{} nights, {} on average</li>",
+            sleep.nights,
+            format_duration(sleep.mean_duration)
+        );
+        if let (Some(bedtime), Some(wake)) = (&sleep.mean_bedtime, &sleep.mean_wake) {
+            let _ = writeln!(out, "<li>Usually asleep at {bedtime}, awake at {wake}</li>");
+        }
+        if let Some(index) = sleep.regularity_index {
+            let _ = writeln!(out, "<li>Regularity index {index:.0}</li>");
+        }
+        let _ = writeln!(out, "</ul>");
+    }
+
+    if !report.habits.is_empty() {
+        let _ = writeln!(out, "<h2>Habits</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Habit</th><th>Passed</th><th>Failed</th><th>Streak</th></tr>"
+        );
+        for habit in &report.habits {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&habit.name),
+                habit.passed,
+                habit.failed,
+                habit.current_streak
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    if !report.anomalies.is_empty() {
+        let _ = writeln!(out, "<h2>Unusual days</h2>\n<ul>");
+        for day in &report.anomalies {
+            let details: Vec<String> = day.deviations.iter().map(describe_deviation).collect();
+            let _ = writeln!(
+                out,
+                "<li><strong>{}</strong>: {}</li>",
+                escape_html(&day.date),
+                escape_html(&details.join("; "))
+            );
+        }
+        let _ = writeln!(out, "</ul>");
+    }
+
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3_600_000;
+
+    fn report() -> Report {
+        Report {
+            period: Period::Week,
+            label: "2026-W12".to_string(),
+            from: 0,
+            to: 7 * 24 * HOUR,
+            tz: "UTC".to_string(),
+            previous_label: "2026-W11".to_string(),
+            to_date: false,
+            tracked: 30 * HOUR,
+            previous_tracked: 20 * HOUR,
+            states: ALL_STATES_DETAILS
+                .iter()
+                .enumerate()
+                .map(|(state, detail)| StateLine {
+                    state,
+                    emoji: detail.emoji,
+                    name: detail.name,
+                    colour: detail.colour,
+                    total: if state == 0 { 30 * HOUR } else { 0 },
+                    previous: if state == 1 { 20 * HOUR } else { 0 },
+                })
+                .collect(),
+            groups: Vec::new(),
+            top_sessions: Vec::new(),
+            sleep: None,
+            habits: vec![HabitLine {
+                name: "<b>Read</b>".to_string(),
+                passed: 5,
+                failed: 2,
+                current_streak: 3,
+            }],
+            anomalies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn formats_durations_and_changes() {
+        assert_eq!(format_duration(35 * 60_000), "35m");
+        assert_eq!(format_duration(4 * HOUR + 5 * 60_000), "4h 05m");
+        assert_eq!(format_duration(-90 * 60_000), "-1h 30m");
+        assert_eq!(format_change(30 * HOUR, 20 * HOUR), "+10h 00m (+50%)");
+        assert_eq!(format_change(0, 20 * HOUR), "-20h 00m (-100%)");
+        assert_eq!(format_change(HOUR, 0), "+1h 00m");
+    }
+
+    #[test]
+    fn markdown_lists_only_states_with_time() {
+        let markdown = render_markdown(&report());
+        assert!(markdown.starts_with("# Weekly review: 2026-W12\n"));
+        assert!(markdown.contains("| 📚 | Study | 30h 00m | +30h 00m |"));
+        assert!(markdown.contains("| 💼 | Work | 0m | -20h 00m (-100%) |"));
+        assert!(!markdown.contains("Commute"));
+        assert!(!markdown.contains("## Sleep"));
+    }
+
+    #[test]
+    fn html_escapes_habit_names() {
+        let html = render_html(&report());
+        assert!(html.contains("<td>&lt;b&gt;Read&lt;/b&gt;</td>"));
+        assert!(!html.contains("<b>Read</b>"));
+    }
+}
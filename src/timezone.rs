@@ -0,0 +1,137 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A timezone that is either a fixed UTC offset, as older clients send with
+//! `tz_offset`, or a named IANA zone that follows DST. Both can then go through
+//! the same code, such as the predictor's hour-of-day context.
+
+use chrono::{FixedOffset, MappedLocalTime, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::{Tz, TzOffset};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl Zone {
+    /// `+02:00` for a fixed offset, the IANA name otherwise.
+    pub fn name(&self) -> String {
+        match self {
+            Zone::Fixed(offset) => offset.to_string(),
+            Zone::Named(tz) => tz.name().to_string(),
+        }
+    }
+}
+
+impl From<Tz> for Zone {
+    fn from(tz: Tz) -> Self {
+        Zone::Named(tz)
+    }
+}
+
+impl From<FixedOffset> for Zone {
+    fn from(offset: FixedOffset) -> Self {
+        Zone::Fixed(offset)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoneOffset {
+    Fixed(FixedOffset),
+    Named(TzOffset),
+}
+
+impl Offset for ZoneOffset {
+    fn fix(&self) -> FixedOffset {
+        match self {
+            ZoneOffset::Fixed(offset) => offset.fix(),
+            ZoneOffset::Named(offset) => offset.fix(),
+        }
+    }
+}
+
+impl fmt::Display for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneOffset::Fixed(offset) => offset.fmt(f),
+            ZoneOffset::Named(offset) => offset.fmt(f),
+        }
+    }
+}
+
+impl TimeZone for Zone {
+    type Offset = ZoneOffset;
+
+    fn from_offset(offset: &ZoneOffset) -> Self {
+        match offset {
+            ZoneOffset::Fixed(offset) => Zone::Fixed(*offset),
+            ZoneOffset::Named(offset) => Zone::Named(Tz::from_offset(offset)),
+        }
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<ZoneOffset> {
+        match self {
+            Zone::Fixed(offset) => offset.offset_from_local_date(local).map(ZoneOffset::Fixed),
+            Zone::Named(tz) => tz.offset_from_local_date(local).map(ZoneOffset::Named),
+        }
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> MappedLocalTime<ZoneOffset> {
+        match self {
+            Zone::Fixed(offset) => offset
+                .offset_from_local_datetime(local)
+                .map(ZoneOffset::Fixed),
+            Zone::Named(tz) => tz.offset_from_local_datetime(local).map(ZoneOffset::Named),
+        }
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneOffset {
+        match self {
+            Zone::Fixed(offset) => ZoneOffset::Fixed(offset.offset_from_utc_date(utc)),
+            Zone::Named(tz) => ZoneOffset::Named(tz.offset_from_utc_date(utc)),
+        }
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneOffset {
+        match self {
+            Zone::Fixed(offset) => ZoneOffset::Fixed(offset.offset_from_utc_datetime(utc)),
+            Zone::Named(tz) => ZoneOffset::Named(tz.offset_from_utc_datetime(utc)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[test]
+    fn named_zones_shift_with_dst_and_fixed_ones_do_not() {
+        let london = Zone::Named(chrono_tz::Europe::London);
+        let fixed = Zone::Fixed(FixedOffset::east_opt(0).unwrap());
+        // 2026-07-15 08:30 UTC, during British Summer Time.
+        let at = 1_784_104_200_000;
+
+        let local = london.timestamp_millis_opt(at).unwrap();
+        assert_eq!(local.hour(), 9);
+        assert_eq!(local.timezone(), london);
+        assert_eq!(fixed.timestamp_millis_opt(at).unwrap().hour(), 8);
+
+        assert_eq!(london.name(), "Europe/London");
+        assert_eq!(fixed.name(), "+00:00");
+    }
+}
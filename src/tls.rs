@@ -0,0 +1,118 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Optional built-in TLS, for running without a reverse proxy in front. The
+//! access key travels in the query string, so anything but a loopback listener
+//! should be behind TLS one way or another.
+
+use axum::{
+    Router,
+    http::{HeaderMap, StatusCode, Uri, header::HOST},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+pub struct TlsSettings {
+    pub cert_path: String,
+    pub key_path: String,
+    /// Where to listen for plain HTTP and redirect it to HTTPS, if anywhere.
+    pub redirect_addr: Option<SocketAddr>,
+}
+
+/// Serves `app` over HTTPS on `addr` until the server stops. The certificate
+/// and key are re-read on SIGHUP, so a renewed certificate can be picked up
+/// without restarting (and without closing the database).
+pub async fn serve_tls(app: Router, addr: SocketAddr, settings: TlsSettings) -> anyhow::Result<()> {
+    let config = RustlsConfig::from_pem_file(&settings.cert_path, &settings.key_path).await?;
+
+    tokio::spawn(reload_on_sighup(
+        config.clone(),
+        settings.cert_path,
+        settings.key_path,
+    ));
+
+    if let Some(redirect_addr) = settings.redirect_addr {
+        let listener = TcpListener::bind(redirect_addr).await?;
+        println!("Redirecting HTTP on {redirect_addr} to HTTPS");
+        tokio::spawn(redirect_to_https(listener, addr.port()));
+    }
+
+    println!("Server running on {addr} (TLS)");
+
+    axum_server::bind_rustls(addr, config)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(config: RustlsConfig, cert_path: String, key_path: String) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("tls: cannot listen for SIGHUP, certificates will not reload: {err}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        // A failed reload keeps serving the previous certificate rather than
+        // taking the server down over a half-written file.
+        match config.reload_from_pem_file(&cert_path, &key_path).await {
+            Ok(()) => println!("tls: reloaded certificate from {cert_path}"),
+            Err(err) => eprintln!("tls: failed to reload certificate from {cert_path}: {err}"),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_sighup(_config: RustlsConfig, _cert_path: String, _key_path: String) {}
+
+async fn redirect_to_https(listener: TcpListener, https_port: u16) {
+    let redirect = Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        https_location(&headers, &uri, https_port)
+    });
+
+    if let Err(err) = axum::serve(listener, redirect).await {
+        eprintln!("tls: HTTP redirect listener stopped: {err}");
+    }
+}
+
+fn https_location(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(host) = headers.get(HOST).and_then(|value| value.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Bad request: Missing Host header").into_response();
+    };
+
+    // Drop whatever port the plain request came in on, keeping bracketed IPv6
+    // literals intact.
+    let hostname = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    let location = if https_port == 443 {
+        format!("https://{hostname}{path}")
+    } else {
+        format!("https://{hostname}:{https_port}{path}")
+    };
+
+    Redirect::permanent(&location).into_response()
+}
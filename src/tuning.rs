@@ -0,0 +1,558 @@
+// TimeTracker - Rust-based web app that tracks and analyses user's daily routine to provide insight in time management.
+// Copyright (C) 2025 Brian Chen (differental)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, version 3.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Replaying a log to choose a predictor configuration, shared by
+//! `examples/eval_predictor.rs` and `POST /api/predictor/tune`. Candidates are
+//! ranked on one window of the log and the best are checked on a later one they
+//! were never picked on, as `docs/predictor-tuning.md` describes.
+
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accuracy::Accuracy,
+    predictor::{ActivityPredictor, Configuration, Ranking, TrainingEntry, WeekdayMode},
+};
+
+#[derive(Default, Clone)]
+pub struct Metrics {
+    pub scored: usize,
+    pub skipped_self: usize,
+    pub top1: usize,
+    pub top3: usize,
+    pub reciprocal_rank: f64,
+}
+
+impl Metrics {
+    pub fn record(&mut self, truth: usize, predictions: &[usize], current: Option<usize>) {
+        if current == Some(truth) {
+            self.skipped_self += 1;
+            return;
+        }
+        self.scored += 1;
+        if predictions.first() == Some(&truth) {
+            self.top1 += 1;
+        }
+        if let Some(rank) = predictions.iter().position(|&s| s == truth) {
+            if rank < 3 {
+                self.top3 += 1;
+            }
+            self.reciprocal_rank += 1.0 / (rank as f64 + 1.0);
+        }
+    }
+
+    pub fn top1_rate(&self) -> f64 {
+        ratio(self.top1, self.scored)
+    }
+
+    pub fn top3_rate(&self) -> f64 {
+        ratio(self.top3, self.scored)
+    }
+
+    pub fn mrr(&self) -> f64 {
+        if self.scored == 0 {
+            0.0
+        } else {
+            self.reciprocal_rank / self.scored as f64
+        }
+    }
+
+    /// The same rates as fractions, the way live accuracy is reported.
+    pub fn accuracy(&self) -> Accuracy {
+        Accuracy {
+            transitions: self.scored,
+            top1: self.top1_rate() / 100.0,
+            top3: self.top3_rate() / 100.0,
+            mrr: self.mrr(),
+        }
+    }
+}
+
+/// `numerator` as a percentage of `denominator`, 0 when that is 0.
+pub fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        100.0 * numerator as f64 / denominator as f64
+    }
+}
+
+/// Replays `entries` through a fresh predictor, scoring its suggestions for
+/// entries `score_from..score_to` before it trains on each.
+pub fn eval_window<Z: TimeZone>(
+    entries: &[TrainingEntry],
+    timezone: Z,
+    configuration: Configuration,
+    score_from: usize,
+    score_to: usize,
+    limit: usize,
+) -> Metrics {
+    let mut predictor = ActivityPredictor::new(&[], timezone, configuration);
+    let mut metrics = Metrics::default();
+    for (i, entry) in entries.iter().enumerate() {
+        if i >= score_to {
+            break;
+        }
+        if i >= score_from.max(1) {
+            let current = entries[i - 1].state_id;
+            let predictions = predictor.predictions(entry.start_timestamp, Some(current), limit);
+            metrics.record(entry.state_id, &predictions, Some(current));
+        }
+        predictor.train(entry.state_id, entry.start_timestamp);
+    }
+    metrics
+}
+
+/// A named set of configurations to try.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSpace {
+    /// The named presets.
+    Presets,
+    /// One knob at a time around the base configuration.
+    StageTwo,
+    /// The knobs that interact, swept together over the iOS configuration.
+    /// Several hundred candidates.
+    Grid,
+    /// One knob at a time over the iOS configuration.
+    Ablations,
+}
+
+impl SearchSpace {
+    pub fn candidates(self, base: &Configuration) -> Vec<(String, Configuration)> {
+        match self {
+            SearchSpace::Presets => vec![
+                ("default".to_string(), Configuration::default()),
+                ("legacy".to_string(), Configuration::legacy()),
+            ],
+            SearchSpace::StageTwo => stage_two(base),
+            SearchSpace::Grid => grid(),
+            SearchSpace::Ablations => ablations(),
+        }
+    }
+}
+
+pub fn stage_two(base: &Configuration) -> Vec<(String, Configuration)> {
+    let mut variants = vec![("base".to_string(), base.clone())];
+
+    for size in [1_024, 4_096, 16_384] {
+        variants.push((
+            format!("tables {size}"),
+            Configuration {
+                base_table_size: size,
+                tagged_table_size: size,
+                ..base.clone()
+            },
+        ));
+    }
+    variants.push((
+        "elapsed context".to_string(),
+        Configuration {
+            elapsed_context: !base.elapsed_context,
+            ..base.clone()
+        },
+    ));
+    for lengths in [
+        vec![1, 2, 3, 4],
+        vec![1, 2, 4, 8],
+        vec![1, 2, 4, 8, 16, 32, 64],
+    ] {
+        variants.push((
+            format!("history {lengths:?}"),
+            Configuration {
+                history_lengths: lengths,
+                ..base.clone()
+            },
+        ));
+    }
+    for counter_max in [3, 15, 31] {
+        variants.push((
+            format!("counter max {counter_max}"),
+            Configuration {
+                counter_max,
+                ..base.clone()
+            },
+        ));
+    }
+    for length_base in [1.0, 1.5, 3.0, 4.0] {
+        variants.push((
+            format!("length base {length_base}"),
+            Configuration {
+                fusion_length_base: length_base,
+                ..base.clone()
+            },
+        ));
+    }
+    for decay in [0.85, 0.9, 0.99] {
+        variants.push((
+            format!("recency decay {decay}"),
+            Configuration {
+                recency_decay: decay,
+                ..base.clone()
+            },
+        ));
+    }
+    for weight in [0.25, 0.5, 0.75] {
+        variants.push((
+            format!("ensemble weight {weight}"),
+            Configuration {
+                ranking: Ranking::Ensemble,
+                ensemble_markov_weight: weight,
+                ..base.clone()
+            },
+        ));
+    }
+    for interval in [64, 1_024] {
+        variants.push((
+            format!("aging interval {interval}"),
+            Configuration {
+                usefulness_aging_interval: interval,
+                ..base.clone()
+            },
+        ));
+    }
+    variants
+}
+
+pub fn grid() -> Vec<(String, Configuration)> {
+    let mut variants = Vec::new();
+    for prior in [0.15, 0.25, 0.4, 0.6, 0.8] {
+        for weekday_mode in [
+            WeekdayMode::Ignored,
+            WeekdayMode::Weekend,
+            WeekdayMode::Exact,
+        ] {
+            for hour_bucket in [1, 2, 3, 4, 6] {
+                for capacity in [3, 5, 8] {
+                    for time_context_tables in [usize::MAX, 2] {
+                        let configuration = Configuration {
+                            ranking: Ranking::Fusion,
+                            fusion_prior_weight: prior,
+                            weekday_mode,
+                            hour_bucket,
+                            candidate_capacity: capacity,
+                            time_context_tables,
+                            ..Configuration::legacy()
+                        };
+                        let scope = if time_context_tables == usize::MAX {
+                            "all".to_string()
+                        } else {
+                            time_context_tables.to_string()
+                        };
+                        variants.push((
+                            format!(
+                                "prior {prior} wd {weekday_mode:?} hb {hour_bucket} cap {capacity} tct {scope}"
+                            ),
+                            configuration,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    variants
+}
+
+pub fn ablations() -> Vec<(String, Configuration)> {
+    let mut variants = Vec::new();
+
+    variants.push(("legacy".to_string(), Configuration::legacy()));
+
+    for size in [1_024, 4_096, 16_384] {
+        variants.push((
+            format!("+ tables {size}"),
+            Configuration {
+                base_table_size: size,
+                tagged_table_size: size,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for bucket in [2, 3, 4, 6, 24] {
+        variants.push((
+            format!("+ hour bucket {bucket}"),
+            Configuration {
+                hour_bucket: bucket,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for (label, mode) in [
+        ("weekend", WeekdayMode::Weekend),
+        ("ignored", WeekdayMode::Ignored),
+    ] {
+        variants.push((
+            format!("+ weekday {label}"),
+            Configuration {
+                weekday_mode: mode,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for tables in [0, 1, 2, 3, 4] {
+        variants.push((
+            format!("+ time in first {tables} tables"),
+            Configuration {
+                time_context_tables: tables,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    variants.push((
+        "+ elapsed context".to_string(),
+        Configuration {
+            elapsed_context: true,
+            ..Configuration::legacy()
+        },
+    ));
+
+    for lengths in [
+        vec![1, 2, 3, 4],
+        vec![1, 2, 3, 4, 6, 8],
+        vec![1, 2, 4, 8],
+        vec![1, 2, 4, 8, 16, 32, 64],
+    ] {
+        variants.push((
+            format!("+ history {lengths:?}"),
+            Configuration {
+                history_lengths: lengths,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for capacity in [4, 6, 8, 15] {
+        variants.push((
+            format!("+ candidates {capacity}"),
+            Configuration {
+                candidate_capacity: capacity,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for counter_max in [3, 15, 31] {
+        variants.push((
+            format!("+ counter max {counter_max}"),
+            Configuration {
+                counter_max,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    variants.push((
+        "+ fusion".to_string(),
+        Configuration {
+            ranking: Ranking::Fusion,
+            ..Configuration::legacy()
+        },
+    ));
+
+    for prior in [0.1, 0.25, 0.5, 1.0] {
+        variants.push((
+            format!("+ fusion, prior {prior}"),
+            Configuration {
+                ranking: Ranking::Fusion,
+                fusion_prior_weight: prior,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for length_base in [1.0, 1.5, 3.0] {
+        variants.push((
+            format!("+ fusion, length base {length_base}"),
+            Configuration {
+                ranking: Ranking::Fusion,
+                fusion_length_base: length_base,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    for decay in [0.9, 0.99, 1.0] {
+        variants.push((
+            format!("+ recency decay {decay}"),
+            Configuration {
+                recency_decay: decay,
+                ..Configuration::legacy()
+            },
+        ));
+    }
+
+    variants
+}
+
+/// How one candidate did.
+#[derive(Clone, Debug, Serialize)]
+pub struct CandidateResult {
+    pub label: String,
+    pub configuration: Configuration,
+    pub selection: Accuracy,
+    /// Only measured for the best few on selection.
+    pub held_out: Option<Accuracy>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TuningReport {
+    /// Entries `selection_from..held_out_from` pick the winner, and entries
+    /// `held_out_from..held_out_to` check it.
+    pub selection_from: usize,
+    pub held_out_from: usize,
+    pub held_out_to: usize,
+    /// Best on selection first, by top-3 and then top-1. The first is the
+    /// winner; ties keep the order the candidates were given in.
+    pub candidates: Vec<CandidateResult>,
+}
+
+/// The latest tuning run started through the API. Only one runs at a time.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TuningJob {
+    #[default]
+    Idle,
+    Running {
+        started_at: i64,
+        /// Evaluations done so far, out of `evaluations`.
+        done: usize,
+        evaluations: usize,
+    },
+    Finished {
+        started_at: i64,
+        finished_at: i64,
+        report: TuningReport,
+        /// The configuration in use when the run started, on held-out.
+        current_held_out: Accuracy,
+        /// Whether the winner was switched to.
+        adopted: bool,
+    },
+    Failed {
+        started_at: i64,
+        finished_at: i64,
+        error: String,
+    },
+}
+
+/// Scores every candidate on the first half of the log after `warmup` entries,
+/// then the best `validate_top` on the second half. `progress` is called with
+/// how many evaluations are done, out of `candidates.len() + validate_top`.
+pub fn tune<Z: TimeZone>(
+    entries: &[TrainingEntry],
+    timezone: Z,
+    candidates: Vec<(String, Configuration)>,
+    warmup: usize,
+    validate_top: usize,
+    limit: usize,
+    mut progress: impl FnMut(usize),
+) -> TuningReport {
+    let selection_from = warmup.min(entries.len());
+    let held_out_from = selection_from + (entries.len() - selection_from) / 2;
+    let held_out_to = entries.len();
+
+    let mut done = 0;
+    let mut results: Vec<CandidateResult> = candidates
+        .into_iter()
+        .map(|(label, configuration)| {
+            let selection = eval_window(
+                entries,
+                timezone.clone(),
+                configuration.clone(),
+                selection_from,
+                held_out_from,
+                limit,
+            );
+            done += 1;
+            progress(done);
+            CandidateResult {
+                label,
+                configuration,
+                selection: selection.accuracy(),
+                held_out: None,
+            }
+        })
+        .collect();
+    results.sort_by(|a, b| {
+        b.selection
+            .top3
+            .total_cmp(&a.selection.top3)
+            .then(b.selection.top1.total_cmp(&a.selection.top1))
+    });
+
+    for result in results.iter_mut().take(validate_top) {
+        let held_out = eval_window(
+            entries,
+            timezone.clone(),
+            result.configuration.clone(),
+            held_out_from,
+            held_out_to,
+            limit,
+        );
+        result.held_out = Some(held_out.accuracy());
+        done += 1;
+        progress(done);
+    }
+
+    TuningReport {
+        selection_from,
+        held_out_from,
+        held_out_to,
+        candidates: results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn picks_on_selection_and_checks_the_best_on_held_out() {
+        let entries: Vec<TrainingEntry> = std::iter::repeat_n([0, 1, 2, 0, 1, 3], 40)
+            .flatten()
+            .enumerate()
+            .map(|(i, state_id)| TrainingEntry {
+                state_id,
+                start_timestamp: 1_700_000_000_000 + i as i64 * 600_000,
+            })
+            .collect();
+        let candidates = SearchSpace::Presets.candidates(&Configuration::default());
+        let mut calls = Vec::new();
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let report = tune(&entries, utc, candidates, 120, 1, 3, |done| {
+            calls.push(done)
+        });
+
+        assert_eq!(calls, [1, 2, 3]);
+        assert_eq!(
+            (
+                report.selection_from,
+                report.held_out_from,
+                report.held_out_to
+            ),
+            (120, 180, 240)
+        );
+        assert_eq!(report.candidates.len(), 2);
+        let winner = &report.candidates[0];
+        assert_eq!(winner.selection.transitions, 60);
+        assert!(winner.selection.top3 >= report.candidates[1].selection.top3);
+        assert_eq!(winner.held_out.unwrap().transitions, 60);
+        assert!(report.candidates[1].held_out.is_none());
+    }
+}
@@ -14,7 +14,17 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use chrono::{LocalResult, TimeZone, Utc};
-use sled::{IVec, Tree};
+use chrono_tz::Tz;
+use sled::{
+    IVec, Tree,
+    transaction::{TransactionalTree, UnabortableTransactionError},
+};
+
+use crate::{
+    accuracy::Challenger,
+    constants::{DEFAULT_TIMEZONE, STATE_COUNT},
+    predictor::Configuration,
+};
 
 pub fn is_valid_timestamp(timestamp: i64) -> bool {
     matches!(Utc.timestamp_millis_opt(timestamp), LocalResult::Single(_))
@@ -74,6 +84,86 @@ pub fn incr_length(meta: &Tree) -> u64 {
     len + 1
 }
 
+/// Bumped whenever history is rewritten rather than appended to, so anything
+/// built up from it incrementally (the cached predictors) knows to start over.
+pub fn get_history_version(meta: &Tree) -> u64 {
+    match meta.get(b"history_version") {
+        Ok(Some(val)) => ivec_to_u64(val),
+        _ => 0,
+    }
+}
+
+/// Takes a transaction so the bump commits with the rewrite it marks: a predictor
+/// must never see one without the other.
+pub fn bump_history_version(meta: &TransactionalTree) -> Result<(), UnabortableTransactionError> {
+    let version = meta.get(b"history_version")?.map_or(0, ivec_to_u64);
+    meta.insert(b"history_version", to_ivec(version + 1))?;
+    Ok(())
+}
+
+/// The predictor configuration set through `/api/predictor/config`, or the
+/// default if none has been. A stored one that no longer parses or validates is
+/// logged and ignored rather than allowed to break suggestions.
+pub fn get_predictor_configuration(meta: &Tree) -> Configuration {
+    let Ok(Some(bytes)) = meta.get(b"predictor_config") else {
+        return Configuration::default();
+    };
+    match serde_json::from_slice::<Configuration>(&bytes) {
+        Ok(configuration) if configuration.validate().is_ok() => configuration,
+        Ok(_) => {
+            eprintln!("get_predictor_configuration: ignoring invalid stored configuration");
+            Configuration::default()
+        }
+        Err(err) => {
+            eprintln!(
+                "get_predictor_configuration: ignoring unreadable stored configuration: {err}"
+            );
+            Configuration::default()
+        }
+    }
+}
+
+pub fn set_predictor_configuration(
+    meta: &Tree,
+    configuration: &Configuration,
+) -> Result<(), String> {
+    let bytes = serde_json::to_vec(configuration).map_err(|err| err.to_string())?;
+    meta.insert(b"predictor_config", bytes)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// The configuration run in shadow through `/api/predictor/challenger`, if any.
+/// An unreadable or invalid one is logged and treated as none.
+pub fn get_predictor_challenger(meta: &Tree) -> Option<Challenger> {
+    let bytes = meta.get(b"predictor_challenger").ok()??;
+    match serde_json::from_slice::<Challenger>(&bytes) {
+        Ok(challenger) if challenger.configuration.validate().is_ok() => Some(challenger),
+        Ok(_) => {
+            eprintln!("get_predictor_challenger: ignoring invalid stored challenger");
+            None
+        }
+        Err(err) => {
+            eprintln!("get_predictor_challenger: ignoring unreadable stored challenger: {err}");
+            None
+        }
+    }
+}
+
+pub fn set_predictor_challenger(
+    meta: &Tree,
+    challenger: Option<&Challenger>,
+) -> Result<(), String> {
+    match challenger {
+        Some(challenger) => {
+            let bytes = serde_json::to_vec(challenger).map_err(|err| err.to_string())?;
+            meta.insert(b"predictor_challenger", bytes).map(|_| ())
+        }
+        None => meta.remove(b"predictor_challenger").map(|_| ()),
+    }
+    .map_err(|err| err.to_string())
+}
+
 pub fn read_from_value(events: &Tree, id: u64) -> (u8, i64) {
     // TO-DO: Handle None and Err(_) gracefully
     let bytes = events.get(id.to_ne_bytes()).unwrap().unwrap();
@@ -94,3 +184,50 @@ pub fn try_read_from_value(events: &Tree, id: u64) -> Option<(u8, i64)> {
     time_bytes.copy_from_slice(&bytes[1..9]);
     Some((state, i64::from_ne_bytes(time_bytes)))
 }
+
+/// Reads every entry in order as `(state, start_timestamp)`, dropping (and
+/// logging) any that can't be placed on a timeline: unreadable rows, state
+/// indices out of range, and unrepresentable timestamps.
+pub fn read_valid_entries(events: &Tree, meta: &Tree, context: &str) -> Vec<(usize, i64)> {
+    let length = get_length(meta);
+    let mut entries = Vec::with_capacity(length as usize);
+    for i in 0..length {
+        let Some((state, timestamp)) = try_read_from_value(events, i) else {
+            eprintln!("{context}: skipping unreadable entry at index {i}");
+            continue;
+        };
+        if state as usize >= STATE_COUNT || !is_valid_timestamp(timestamp) {
+            log_corrupt_entry(context, i, state, timestamp);
+            continue;
+        }
+        entries.push((state as usize, timestamp));
+    }
+    entries
+}
+
+/// Parses an IANA timezone name such as `Europe/London`, defaulting to the
+/// server's `TIMEZONE` when none is given. `None` means the name is not a known
+/// timezone.
+pub fn parse_timezone(name: Option<&str>) -> Option<Tz> {
+    match name {
+        Some(name) => name.trim().parse().ok(),
+        None => Some(*DEFAULT_TIMEZONE),
+    }
+}
+
+/// Parses a comma-separated list of state indices such as `0,1,3`. `None` if any
+/// of them is not a number or not a valid state.
+pub fn parse_states(list: &str) -> Option<Vec<usize>> {
+    let mut states = Vec::new();
+    for item in list
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let state = item.parse::<usize>().ok().filter(|&s| s < STATE_COUNT)?;
+        if !states.contains(&state) {
+            states.push(state);
+        }
+    }
+    (!states.is_empty()).then_some(states)
+}